@@ -0,0 +1,437 @@
+use anyhow::Result;
+use chrono::{Local, NaiveDateTime, TimeZone};
+use std::collections::HashMap;
+use std::path::Path;
+use takure_common::fingerprint;
+use takure_common::types::game::{Property2, Property3, Result as StageResult};
+use takure_common::types::tachi::{
+    Difficulty, Flare, HitMeta, Import, ImportMeta, ImportScore, Judgements, Optional, Playtype, TachiLamp,
+};
+
+/// A score recovered from a log file
+#[derive(Debug, Clone)]
+pub struct Recovered {
+    pub card: String,
+    pub import: Import,
+    /// Whether the time was read from a request dump, rather than approximated from the log line
+    pub exact_time: bool,
+}
+
+struct Line<'a> {
+    time: u128,
+    message: &'a str,
+}
+
+fn strip_ansi(line: &str) -> String {
+    let mut stripped = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // Skip until the end of the escape sequence
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            stripped.push(c);
+        }
+    }
+
+    stripped
+}
+
+/// Parses a `[dd/mm/YYYY HH:MM:SS] LEVEL target -> message` log line
+fn parse_line(line: &str) -> Option<Line<'_>> {
+    let rest = line.strip_prefix('[')?;
+    let (time, rest) = rest.split_once("] ")?;
+    let (_, message) = rest.split_once(" -> ")?;
+
+    let time = NaiveDateTime::parse_from_str(time, "%d/%m/%Y %H:%M:%S").ok()?;
+    let time = Local.from_local_datetime(&time).earliest()?.timestamp_millis();
+
+    Some(Line {
+        time: time as u128,
+        message,
+    })
+}
+
+fn from_property(property: &str, time: u128) -> Option<Import> {
+    let (score, meta) = if let Ok(property) = serde_json::from_str::<Property2>(property) {
+        let data = property.call.playerdata_2.data;
+        if data.mode != "usersave" || data.isgameover || data.ref_id.starts_with("X000") {
+            return None;
+        }
+        data.highest_stage()?.to_import(time).ok()?
+    } else if let Ok(property) = serde_json::from_str::<Property3>(property) {
+        let data = property.call.playdata_3.data;
+        if data.savekind != 2 || data.ref_id.starts_with("X000") {
+            return None;
+        }
+        serde_json::from_value::<StageResult>(data.result).ok()?.to_import(time).ok()?
+    } else {
+        return None;
+    };
+
+    Some(Import {
+        meta,
+        scores: vec![score],
+    })
+}
+
+/// Reads the `field: value` lines of a pretty-printed Debug dump
+fn debug_fields(dump: &str) -> HashMap<&str, &str> {
+    let mut fields = HashMap::new();
+    for line in dump.lines() {
+        if let Some((key, value)) = line.trim().trim_end_matches(',').split_once(": ") {
+            fields.entry(key).or_insert_with(|| value.trim_matches('"'));
+        }
+    }
+
+    fields
+}
+
+fn lamp(name: &str) -> Option<TachiLamp> {
+    Some(match name {
+        "Failed" => TachiLamp::Failed,
+        "Assist" => TachiLamp::Assist,
+        "Clear" => TachiLamp::Clear,
+        "Life4" => TachiLamp::Life4,
+        "FullCombo" => TachiLamp::FullCombo,
+        "GreatFullCombo" => TachiLamp::GreatFullCombo,
+        "PerfectFullCombo" => TachiLamp::PerfectFullCombo,
+        "MarvelousFullCombo" => TachiLamp::MarvelousFullCombo,
+        _ => return None,
+    })
+}
+
+fn difficulty(name: &str) -> Option<Difficulty> {
+    Some(match name {
+        "Beginner" => Difficulty::Beginner,
+        "Basic" => Difficulty::Basic,
+        "Difficult" => Difficulty::Difficult,
+        "Expert" => Difficulty::Expert,
+        "Challenge" => Difficulty::Challenge,
+        _ => return None,
+    })
+}
+
+fn flare(name: &str) -> Flare {
+    const FLARES: [&str; 10] = ["None", "I", "II", "III", "IV", "V", "VI", "VII", "VIII", "IX"];
+    FLARES
+        .iter()
+        .position(|flare| *flare == name)
+        .map(|flare| Flare::from(flare as u8))
+        .unwrap_or(Flare::None)
+}
+
+/// Rebuilds an import from a `Tachi API request data: {:#?}` dump
+fn from_request_dump(dump: &str) -> Option<Import> {
+    let (meta, scores) = dump.split_once("scores:")?;
+    let play_type = match debug_fields(meta).get("play_type") {
+        Some(&"DP") => Playtype::DP,
+        _ => Playtype::SP,
+    };
+
+    let mut imported = Vec::new();
+    for score in scores.split("ImportScore {").skip(1) {
+        let fields = debug_fields(score);
+        let number = |key: &str| fields.get(key).and_then(|value| value.parse::<u32>().ok());
+        imported.push(ImportScore {
+            score: number("score")?,
+            lamp: lamp(fields.get("lamp")?)?,
+            match_type: "inGameID".to_string(),
+            identifier: fields.get("identifier")?.to_string(),
+            difficulty: difficulty(fields.get("difficulty")?)?,
+            time_achieved: fields.get("time_achieved")?.parse().ok()?,
+            judgements: Judgements {
+                marvelous: number("marvelous")?,
+                perfect: number("perfect")?,
+                great: number("great")?,
+                good: number("good")?,
+                miss: number("miss")?,
+                ok: number("ok")?,
+            },
+            hit_meta: HitMeta {
+                fast: number("fast")?,
+                slow: number("slow")?,
+                max_combo: number("max_combo")?,
+                ex_score: number("ex_score")?,
+            },
+            optional: Optional {
+                flare: fields.get("flare").map(|name| flare(name)).unwrap_or_default(),
+            },
+            fingerprint: String::new(),
+        });
+    }
+
+    if imported.is_empty() {
+        return None;
+    }
+
+    Some(Import {
+        meta: ImportMeta {
+            play_type,
+            ..Default::default()
+        },
+        scores: imported,
+    })
+}
+
+/// Recovers every score found in a log file, in order
+pub fn parse_log(path: &Path) -> Result<Vec<Recovered>> {
+    let content = std::fs::read(path)
+        .map_err(|err| anyhow::anyhow!("Could not read '{}': {:#}", path.display(), err))?;
+
+    Ok(parse(&String::from_utf8_lossy(&content)))
+}
+
+fn parse(content: &str) -> Vec<Recovered> {
+    let lines = content.lines().map(strip_ansi).collect::<Vec<_>>();
+
+    let mut recovered = Vec::new();
+    let mut card = String::new();
+    let mut index = 0;
+    while index < lines.len() {
+        let line = match parse_line(&lines[index]) {
+            Some(line) => line,
+            None => {
+                index += 1;
+                continue;
+            }
+        };
+        index += 1;
+
+        if let Some(cardid) = line.message.strip_prefix("Set current card id to ") {
+            card = cardid.split_whitespace().next().unwrap_or_default().to_string();
+        } else if let Some(property) = line.message.strip_prefix("Processing property: ") {
+            if let Some(import) = from_property(property, line.time) {
+                recovered.push(Recovered {
+                    card: card.clone(),
+                    import,
+                    exact_time: false,
+                });
+            }
+        } else if let Some(dump) = line.message.strip_prefix("Tachi API request data: ") {
+            // The dump spans every line until the next log line
+            let mut dump = dump.to_string();
+            while index < lines.len() && parse_line(&lines[index]).is_none() {
+                dump.push('\n');
+                dump.push_str(&lines[index]);
+                index += 1;
+            }
+
+            if let Some(import) = from_request_dump(&dump) {
+                recovered.push(Recovered {
+                    card: card.clone(),
+                    import,
+                    exact_time: true,
+                });
+            }
+        }
+    }
+
+    recovered
+}
+
+type Key = (String, String, String, u32, [u32; 6]);
+
+fn key(card: &str, score: &ImportScore) -> Key {
+    let judgements = &score.judgements;
+    (
+        card.to_string(),
+        score.identifier.clone(),
+        format!("{:?}", score.difficulty),
+        score.score,
+        [
+            judgements.marvelous,
+            judgements.perfect,
+            judgements.great,
+            judgements.good,
+            judgements.miss,
+            judgements.ok,
+        ],
+    )
+}
+
+/// Removes duplicated scores, which appear both as a property and a request dump, or in several
+/// log files, keeping the most precise time
+///
+/// Scores are split into one import each, and get their fingerprint computed.
+pub fn dedup(recovered: Vec<Recovered>) -> Vec<Recovered> {
+    let mut deduped: Vec<Recovered> = Vec::new();
+    let mut seen: HashMap<Key, Vec<usize>> = HashMap::new();
+
+    for entry in recovered {
+        for mut score in entry.import.scores {
+            score.fingerprint = fingerprint::compute(&entry.card, &score);
+            let single = Recovered {
+                card: entry.card.clone(),
+                import: Import {
+                    meta: entry.import.meta.clone(),
+                    scores: vec![score],
+                },
+                exact_time: entry.exact_time,
+            };
+
+            let indices = seen.entry(key(&single.card, &single.import.scores[0])).or_default();
+            let second = single.import.scores[0].time_achieved / 1000;
+            let duplicate = indices.iter().copied().find(|index| {
+                let other = deduped[*index].import.scores[0].time_achieved / 1000;
+                second.abs_diff(other) <= 1
+            });
+
+            match duplicate {
+                Some(index) => {
+                    if single.exact_time && !deduped[index].exact_time {
+                        deduped[index] = single;
+                    }
+                }
+                None => {
+                    indices.push(deduped.len());
+                    deduped.push(single);
+                }
+            }
+        }
+    }
+
+    deduped
+}
+
+/// Time after the log line of a property within which the hook timestamped its scores, log lines
+/// only being precise to the second
+const TIME_WINDOW: u128 = 2000;
+
+/// Gives scores whose time was approximated from the log line the exact time of the play, when a
+/// play with the same fingerprint was already recorded
+///
+/// The fingerprint of a play includes the millisecond it was processed at, so that it is otherwise
+/// never recognized as already submitted.
+pub fn match_recorded(recovered: &mut [Recovered], is_recorded: impl Fn(&str) -> bool) {
+    for entry in recovered.iter_mut().filter(|entry| !entry.exact_time) {
+        let score = &mut entry.import.scores[0];
+        let second = score.time_achieved;
+        for time in second..second + TIME_WINDOW {
+            score.time_achieved = time;
+            let fingerprint = fingerprint::compute(&entry.card, score);
+            if is_recorded(&fingerprint) {
+                score.fingerprint = fingerprint;
+                entry.exact_time = true;
+                break;
+            }
+        }
+
+        if !entry.exact_time {
+            score.time_achieved = second;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    const CARD: &str = "E004010027A5FC68";
+
+    fn property(difficulty: u8, score: u32) -> String {
+        serde_json::json!({
+            "call": {
+                "playdata_3": {
+                    "data": {
+                        "refid": "ABCDEF0123456789",
+                        "savekind": 2,
+                        "result": {
+                            "stagenum": 1, "mcode": 38422, "difficulty": difficulty,
+                            "clearkind": 2, "score": score, "exscore": 600, "maxcombo": 200,
+                            "fastcount": 3, "slowcount": 4, "judge_marv": 180, "judge_perf": 15,
+                            "judge_great": 5, "judge_good": 0, "judge_miss": 0, "judge_ok": 20,
+                            "style": 0, "flare_force": 0
+                        }
+                    }
+                }
+            }
+        })
+        .to_string()
+    }
+
+    fn log(lines: &[&str]) -> String {
+        lines
+            .iter()
+            .map(|line| format!("[19/10/2026 21:04:05] DEBUG takure::takure -> {}\n", line))
+            .collect()
+    }
+
+    fn log_time() -> u128 {
+        parse_line("[19/10/2026 21:04:05] DEBUG takure -> ").unwrap().time
+    }
+
+    #[test]
+    fn parses_properties() {
+        let content = log(&[
+            &format!("Set current card id to {} (S6E5-23E3-0ZK7-ML1P)", CARD),
+            &format!("Processing property: {}", property(3, 987650)),
+        ]);
+
+        let recovered = parse(&content);
+        assert_eq!(recovered.len(), 1);
+        assert_eq!(recovered[0].card, CARD);
+        assert!(!recovered[0].exact_time);
+        let score = &recovered[0].import.scores[0];
+        assert_eq!(score.identifier, "38422");
+        assert_eq!(score.difficulty, Difficulty::Expert);
+        assert_eq!(score.score, 987650);
+        assert_eq!(score.time_achieved, log_time());
+    }
+
+    #[test]
+    fn skips_unknown_values() {
+        let content = log(&[
+            &format!("Processing property: {}", property(9, 987650)),
+            "Processing property: {\"call\": {}}",
+            &format!("Processing property: {}", property(4, 1000000)),
+        ]);
+
+        let recovered = parse(&content);
+        assert_eq!(recovered.len(), 1);
+        assert_eq!(recovered[0].import.scores[0].difficulty, Difficulty::Challenge);
+    }
+
+    #[test]
+    fn dedups_properties_and_dumps() {
+        let mut content = log(&[
+            &format!("Set current card id to {}", CARD),
+            &format!("Processing property: {}", property(3, 987650)),
+        ]);
+        let mut import = parse(&content)[0].import.clone();
+        import.scores[0].time_achieved = log_time() + 437;
+        content.push_str(&log(&[&format!("Tachi API request data: {:#?}", import)]));
+
+        let recovered = dedup(parse(&content));
+        assert_eq!(recovered.len(), 1);
+        assert!(recovered[0].exact_time);
+        assert_eq!(recovered[0].import.scores[0].time_achieved, log_time() + 437);
+    }
+
+    #[test]
+    fn matches_recorded_fingerprints() {
+        let content = log(&[
+            &format!("Set current card id to {}", CARD),
+            &format!("Processing property: {}", property(3, 987650)),
+            &format!("Processing property: {}", property(4, 1000000)),
+        ]);
+        let mut recovered = dedup(parse(&content));
+
+        // The first play was recorded live, processed a moment after its log line
+        let mut live = recovered[0].import.scores[0].clone();
+        live.time_achieved += 1234;
+        let recorded = HashSet::from([fingerprint::compute(CARD, &live)]);
+
+        match_recorded(&mut recovered, |fingerprint| recorded.contains(fingerprint));
+        assert_eq!(recovered[0].import.scores[0].fingerprint, fingerprint::compute(CARD, &live));
+        assert_eq!(recovered[0].import.scores[0].time_achieved, live.time_achieved);
+        assert!(recovered[0].exact_time);
+        assert_eq!(recovered[1].import.scores[0].time_achieved, log_time());
+        assert!(!recovered[1].exact_time);
+    }
+}
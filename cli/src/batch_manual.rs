@@ -0,0 +1,260 @@
+use anyhow::Result;
+use crate::database::{Filter, Score};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+const FLARES: [&str; 10] = ["", "I", "II", "III", "IV", "V", "VI", "VII", "VIII", "IX"];
+
+/// Returns the name of a flare rank as Tachi expects it, if any
+pub fn flare_name(flare: Option<u8>) -> Option<&'static str> {
+    flare
+        .and_then(|flare| FLARES.get(flare as usize))
+        .copied()
+        .filter(|flare| !flare.is_empty())
+}
+
+/// Converts a recorded score to a score of Tachi BATCH-MANUAL format, the same one Takure submits
+pub fn from_score(score: &Score) -> Value {
+    let mut value = json!({
+        "score": score.score,
+        "lamp": score.lamp,
+        "matchType": "inGameID",
+        "identifier": score.mcode,
+        "difficulty": score.difficulty,
+        "timeAchieved": score.time_achieved,
+        "judgements": {
+            "MARVELOUS": score.marvelous,
+            "PERFECT": score.perfect,
+            "GREAT": score.great,
+            "GOOD": score.good,
+            "MISS": score.miss,
+            "OK": score.ok,
+        },
+        "hitMeta": {
+            "fast": score.fast,
+            "slow": score.slow,
+            "maxCombo": score.max_combo,
+            "exScore": score.ex_score,
+        },
+    });
+
+    if let Some(flare) = flare_name(score.flare) {
+        value["optional"] = json!({ "flare": flare });
+    }
+
+    value
+}
+
+/// Reads the scores of the local queue, grouped by playtype
+pub fn from_queue(path: &Path, filter: &Filter) -> Result<BTreeMap<String, Vec<Value>>> {
+    let file = File::open(path)
+        .map_err(|err| anyhow::anyhow!("Could not open '{}': {:#}", path.display(), err))?;
+
+    let mut scores = BTreeMap::<String, Vec<Value>>::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let entry: Value = serde_json::from_str(&line)
+            .map_err(|err| anyhow::anyhow!("Malformed queued score: {:#}", err))?;
+        let card = entry["card"].as_str().unwrap_or_default();
+        if let Some(filter_card) = &filter.card {
+            if !card.eq_ignore_ascii_case(filter_card) {
+                continue;
+            }
+        }
+
+        let playtype = entry["import"]["meta"]["playtype"].as_str().unwrap_or("SP");
+        let queued = entry["import"]["scores"].as_array().cloned().unwrap_or_default();
+        for score in queued {
+            let time = score["timeAchieved"].as_i64().unwrap_or_default();
+            if filter.since.map(|since| time < since).unwrap_or(false)
+                || filter.until.map(|until| time >= until).unwrap_or(false)
+                || filter
+                    .song
+                    .as_ref()
+                    .map(|song| {
+                        let identifier = score["identifier"].as_str().unwrap_or_default();
+                        identifier != song && !filter.song_codes.iter().any(|code| code == identifier)
+                    })
+                    .unwrap_or(false)
+            {
+                continue;
+            }
+
+            scores.entry(playtype.to_string()).or_default().push(score);
+        }
+    }
+
+    Ok(scores)
+}
+
+/// Groups recorded scores by playtype
+pub fn from_scores(scores: &[Score]) -> BTreeMap<String, Vec<Value>> {
+    let mut grouped = BTreeMap::<String, Vec<Value>>::new();
+    for score in scores {
+        grouped
+            .entry(score.playtype.clone())
+            .or_default()
+            .push(from_score(score));
+    }
+
+    grouped
+}
+
+/// Builds a BATCH-MANUAL file, which can be imported from Tachi web interface
+pub fn build(playtype: &str, scores: Vec<Value>) -> Value {
+    json!({
+        "meta": {
+            "game": "ddr",
+            "playtype": playtype,
+            "service": "Takure",
+        },
+        "scores": scores,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(playtype: &str, difficulty: &str, flare: Option<u8>, time_achieved: i64) -> Score {
+        Score {
+            card: "E004010027A5FC68".to_string(),
+            ref_id: "ABCDEF0123456789".to_string(),
+            playtype: playtype.to_string(),
+            mcode: "38422".to_string(),
+            title: Some("MAX 300".to_string()),
+            difficulty: difficulty.to_string(),
+            score: 987650,
+            ex_score: 600,
+            lamp: "CLEAR".to_string(),
+            flare,
+            max_combo: 200,
+            fast: 3,
+            slow: 4,
+            marvelous: 180,
+            perfect: 15,
+            great: 5,
+            good: 0,
+            miss: 0,
+            ok: 20,
+            time_achieved,
+            status: "submitted".to_string(),
+            reason: None,
+            response: None,
+        }
+    }
+
+    fn keys(value: &Value) -> Vec<&str> {
+        let mut keys = value
+            .as_object()
+            .map(|object| object.keys().map(String::as_str).collect::<Vec<_>>())
+            .unwrap_or_default();
+        keys.sort();
+        keys
+    }
+
+    /// Builds the documents of the grouped scores, and checks they are valid BATCH-MANUAL files
+    fn documents(grouped: BTreeMap<String, Vec<Value>>) -> BTreeMap<String, Value> {
+        grouped
+            .into_iter()
+            .map(|(playtype, scores)| {
+                let document = build(&playtype, scores);
+                assert_eq!(keys(&document), ["meta", "scores"]);
+                assert_eq!(
+                    document["meta"],
+                    json!({ "game": "ddr", "playtype": playtype, "service": "Takure" })
+                );
+                for score in document["scores"].as_array().unwrap() {
+                    let mut expected = vec![
+                        "difficulty",
+                        "hitMeta",
+                        "identifier",
+                        "judgements",
+                        "lamp",
+                        "matchType",
+                        "score",
+                        "timeAchieved",
+                    ];
+                    if score.get("optional").is_some() {
+                        expected.push("optional");
+                        expected.sort();
+                    }
+                    assert_eq!(keys(score), expected);
+                    assert_eq!(score["matchType"], "inGameID");
+                    assert_eq!(
+                        keys(&score["judgements"]),
+                        ["GOOD", "GREAT", "MARVELOUS", "MISS", "OK", "PERFECT"]
+                    );
+                    assert_eq!(keys(&score["hitMeta"]), ["exScore", "fast", "maxCombo", "slow"]);
+                }
+                (playtype, document)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn exports_recorded_scores_by_playtype() {
+        let scores = [
+            score("SP", "EXPERT", Some(5), 1000),
+            score("DP", "DIFFICULT", None, 2000),
+            score("SP", "CHALLENGE", None, 3000),
+        ];
+
+        let documents = documents(from_scores(&scores));
+        assert_eq!(documents.keys().collect::<Vec<_>>(), ["DP", "SP"]);
+
+        let sp = documents["SP"]["scores"].as_array().unwrap();
+        assert_eq!(sp.len(), 2);
+        assert_eq!(sp[0]["difficulty"], "EXPERT");
+        assert_eq!(sp[0]["optional"], json!({ "flare": "V" }));
+        assert_eq!(sp[0]["timeAchieved"], 1000);
+        assert_eq!(sp[1]["difficulty"], "CHALLENGE");
+
+        let dp = documents["DP"]["scores"].as_array().unwrap();
+        assert_eq!(dp.len(), 1);
+        assert_eq!(dp[0]["difficulty"], "DIFFICULT");
+        assert_eq!(dp[0]["identifier"], "38422");
+        assert_eq!(dp[0]["judgements"]["MARVELOUS"], 180);
+        assert_eq!(dp[0]["hitMeta"]["exScore"], 600);
+    }
+
+    #[test]
+    fn exports_queued_scores_in_range() {
+        let queued = |playtype: &str, time_achieved: i64| {
+            json!({
+                "card": "E004010027A5FC68",
+                "import": {
+                    "meta": { "game": "ddr", "playtype": playtype, "service": "Takure" },
+                    "scores": [from_score(&score(playtype, "EXPERT", None, time_achieved))],
+                },
+            })
+            .to_string()
+        };
+        let path = std::env::temp_dir().join(format!("takure-cli-queue-{}.jsonl", std::process::id()));
+        let lines = [queued("SP", 1000), queued("DP", 2000), String::new(), queued("SP", 5000)];
+        std::fs::write(&path, lines.join("\n")).unwrap();
+
+        let filter = Filter {
+            since: Some(1000),
+            until: Some(3000),
+            ..Default::default()
+        };
+        let grouped = from_queue(&path, &filter);
+        std::fs::remove_file(&path).unwrap();
+
+        let documents = documents(grouped.unwrap());
+        assert_eq!(documents.keys().collect::<Vec<_>>(), ["DP", "SP"]);
+        for (playtype, time) in [("SP", 1000), ("DP", 2000)] {
+            let scores = documents[playtype]["scores"].as_array().unwrap();
+            assert_eq!(scores.len(), 1);
+            assert_eq!(scores[0]["timeAchieved"], time);
+        }
+    }
+}
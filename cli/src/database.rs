@@ -0,0 +1,160 @@
+use anyhow::Result;
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection, OpenFlags, Row};
+use serde::Serialize;
+use std::path::Path;
+
+/// Oldest schema version of the score history this tool can read
+const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Score {
+    pub card: String,
+    pub ref_id: String,
+    pub playtype: String,
+    pub mcode: String,
+    /// Title from the game music database, only recorded by newer versions of Takure
+    pub title: Option<String>,
+    pub difficulty: String,
+    pub score: u32,
+    pub ex_score: u32,
+    pub lamp: String,
+    pub flare: Option<u8>,
+    pub max_combo: u32,
+    pub fast: u32,
+    pub slow: u32,
+    pub marvelous: u32,
+    pub perfect: u32,
+    pub great: u32,
+    pub good: u32,
+    pub miss: u32,
+    pub ok: u32,
+    pub time_achieved: i64,
+    pub status: String,
+    pub reason: Option<String>,
+    pub response: Option<serde_json::Value>,
+}
+
+impl Score {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            card: row.get("card")?,
+            ref_id: row.get("ref_id")?,
+            playtype: row.get("playtype")?,
+            mcode: row.get("mcode")?,
+            // The column is only added once a newer version of Takure opened the score history
+            title: row.get("title").unwrap_or(None),
+            difficulty: row.get("difficulty")?,
+            score: row.get("score")?,
+            ex_score: row.get("ex_score")?,
+            lamp: row.get("lamp")?,
+            flare: row.get("flare")?,
+            max_combo: row.get("max_combo")?,
+            fast: row.get("fast")?,
+            slow: row.get("slow")?,
+            marvelous: row.get("marvelous")?,
+            perfect: row.get("perfect")?,
+            great: row.get("great")?,
+            good: row.get("good")?,
+            miss: row.get("miss")?,
+            ok: row.get("ok")?,
+            time_achieved: row.get("time_achieved")?,
+            status: row.get("status")?,
+            reason: row.get("reason")?,
+            response: row
+                .get::<_, Option<String>>("response")?
+                .and_then(|response| serde_json::from_str(&response).ok()),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub card: Option<String>,
+    /// Song code, or part of a title
+    pub song: Option<String>,
+    /// Codes of the songs whose title in the game music database contains `song`
+    pub song_codes: Vec<String>,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub statuses: Vec<String>,
+}
+
+pub struct Database {
+    connection: Connection,
+    /// Whether titles are recorded, only from the second schema version
+    has_titles: bool,
+}
+
+impl Database {
+    pub fn open(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Err(anyhow::anyhow!("Score history '{}' does not exist", path.display()));
+        }
+
+        let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(|err| anyhow::anyhow!("Could not open score history: {:#}", err))?;
+        let version: u32 = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version < SCHEMA_VERSION {
+            return Err(anyhow::anyhow!(
+                "Score history '{}' is empty or was not created by Takure",
+                path.display()
+            ));
+        }
+
+        let has_titles = connection.prepare("SELECT title FROM scores LIMIT 0").is_ok();
+        Ok(Self { connection, has_titles })
+    }
+
+    /// Returns the scores matching the filter, most recent first
+    pub fn scores(&self, filter: &Filter) -> Result<Vec<Score>> {
+        let mut conditions = Vec::new();
+        let mut values = Vec::new();
+        if let Some(card) = &filter.card {
+            conditions.push("(UPPER(card) = UPPER(?) OR UPPER(ref_id) = UPPER(?))".to_string());
+            values.push(Value::Text(card.clone()));
+            values.push(Value::Text(card.clone()));
+        }
+        if let Some(song) = &filter.song {
+            let mut matches = vec!["mcode = ?".to_string()];
+            values.push(Value::Text(song.clone()));
+            if self.has_titles {
+                matches.push("title LIKE ? ESCAPE '\\'".to_string());
+                let escaped = song.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+                values.push(Value::Text(format!("%{}%", escaped)));
+            }
+            if !filter.song_codes.is_empty() {
+                matches.push(format!("mcode IN ({})", vec!["?"; filter.song_codes.len()].join(", ")));
+                values.extend(filter.song_codes.iter().cloned().map(Value::Text));
+            }
+            conditions.push(format!("({})", matches.join(" OR ")));
+        }
+        if let Some(since) = filter.since {
+            conditions.push("time_achieved >= ?".to_string());
+            values.push(Value::Integer(since));
+        }
+        if let Some(until) = filter.until {
+            conditions.push("time_achieved < ?".to_string());
+            values.push(Value::Integer(until));
+        }
+        if !filter.statuses.is_empty() {
+            let placeholders = vec!["?"; filter.statuses.len()].join(", ");
+            conditions.push(format!("status IN ({})", placeholders));
+            values.extend(filter.statuses.iter().cloned().map(Value::Text));
+        }
+
+        let mut query = "SELECT * FROM scores".to_string();
+        if !conditions.is_empty() {
+            query.push_str(" WHERE ");
+            query.push_str(&conditions.join(" AND "));
+        }
+        query.push_str(" ORDER BY time_achieved DESC");
+
+        let mut statement = self.connection.prepare(&query)?;
+        let scores = statement
+            .query_map(params_from_iter(values), Score::from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(scores)
+    }
+}
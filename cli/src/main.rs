@@ -0,0 +1,617 @@
+mod backfill;
+mod batch_manual;
+mod database;
+mod output;
+
+use anyhow::Result;
+use chrono::{Local, NaiveDate, TimeZone};
+use clap::{Parser, Subcommand, ValueEnum};
+use database::{Database, Filter, Score};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use takure_common::{cards, fingerprint, music};
+
+/// Query the local score history recorded by Takure
+#[derive(Debug, Parser)]
+#[command(name = "takure-cli", version)]
+struct Cli {
+    /// Path to the score history database [default: takure.db in TAKURE_DIR, or the current directory]
+    #[arg(long, global = true)]
+    database: Option<PathBuf>,
+    /// Game music database to read song titles from [default: data/gamedata/musicdb.xml, if it exists]
+    #[arg(long, global = true)]
+    musicdb: Option<PathBuf>,
+    /// Print JSON instead of tables
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, clap::Args)]
+struct FilterArgs {
+    /// Only show scores of this card (UID or printed card number) or refid
+    #[arg(long)]
+    card: Option<String>,
+    /// Only show scores of this song, by code or part of its title
+    #[arg(long)]
+    song: Option<String>,
+    /// Only show scores achieved on or after this date (YYYY-MM-DD or RFC 3339)
+    #[arg(long, value_parser = parse_date)]
+    since: Option<i64>,
+    /// Only show scores achieved before this date (YYYY-MM-DD or RFC 3339)
+    #[arg(long, value_parser = parse_date)]
+    until: Option<i64>,
+    /// Only show scores with this submission status (submitted, queued, skipped, rejected, unsubmitted, failed)
+    #[arg(long)]
+    status: Vec<String>,
+}
+
+/// Song titles by song code
+type Titles = HashMap<String, String>;
+
+impl FilterArgs {
+    fn to_filter(&self, titles: &Titles) -> Filter {
+        let song_codes = match &self.song {
+            Some(song) => {
+                let song = song.to_lowercase();
+                titles
+                    .iter()
+                    .filter(|(_, title)| title.to_lowercase().contains(&song))
+                    .map(|(mcode, _)| mcode.clone())
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+
+        Filter {
+            // Printed card numbers are recorded as their UID, refids are kept as they are
+            card: self
+                .card
+                .as_ref()
+                .map(|card| cards::normalize(card).unwrap_or_else(|| card.trim().to_string())),
+            song: self.song.clone(),
+            song_codes,
+            since: self.since,
+            until: self.until,
+            statuses: self.status.clone(),
+        }
+    }
+}
+
+const MUSICDB_PATH: &str = "data/gamedata/musicdb.xml";
+
+/// Reads song titles from the game music database, if there is one
+fn read_titles(path: Option<&Path>) -> Result<Titles> {
+    let path = match path {
+        Some(path) => path,
+        None if Path::new(MUSICDB_PATH).exists() => Path::new(MUSICDB_PATH),
+        None => return Ok(Titles::new()),
+    };
+
+    let content = std::fs::read(path)
+        .map_err(|err| anyhow::anyhow!("Could not read '{}': {:#}", path.display(), err))?;
+    Ok(music::parse(&String::from_utf8_lossy(&content)))
+}
+
+/// Returns the scores matching the filter, with the titles of the music database for scores
+/// recorded without one
+fn scores(database: &Database, filter: &Filter, titles: &Titles) -> Result<Vec<Score>> {
+    let mut scores = database.scores(filter)?;
+    for score in scores.iter_mut().filter(|score| score.title.is_none()) {
+        score.title = titles.get(&score.mcode).cloned();
+    }
+
+    Ok(scores)
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// List recorded scores, most recent first
+    History {
+        #[command(flatten)]
+        filter: FilterArgs,
+        /// Maximum number of scores to show
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+    /// List the best score of each chart
+    Pbs {
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Show statistics over a play session
+    Stats {
+        #[command(flatten)]
+        filter: FilterArgs,
+        /// Which scores to compute statistics on
+        #[arg(long, value_enum, default_value_t = Session::All)]
+        session: Session,
+        /// Minutes without any score after which a new session starts
+        #[arg(long, default_value_t = 60)]
+        gap: i64,
+    },
+    /// Export recorded scores as JSON
+    Export {
+        #[command(flatten)]
+        filter: FilterArgs,
+        /// Write to this file instead of the standard output
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Export scores as Tachi BATCH-MANUAL files, one per playtype, to import them from Tachi website
+    ///
+    /// Skipped and rejected scores are not exported, unless asked with --status.
+    BatchManual {
+        #[command(flatten)]
+        filter: FilterArgs,
+        /// Export the scores waiting in this queue file instead of the score history
+        #[arg(long)]
+        queue: Option<PathBuf>,
+        /// Directory in which files are written
+        #[arg(long, default_value = ".")]
+        output_dir: PathBuf,
+    },
+    /// Recover scores from takure.log files, and export them as BATCH-MANUAL files or submit them
+    ///
+    /// Scores are read from debug logs ("Processing property" lines and "Tachi API request data"
+    /// dumps). Scores listed in takure.submitted, in TAKURE_DIR or the current directory, are skipped.
+    Backfill {
+        /// Log files to read
+        #[arg(required = true)]
+        logs: Vec<PathBuf>,
+        /// Submit the scores to the main Tachi instance (the one in [tachi]) instead of exporting them
+        #[arg(long)]
+        submit: bool,
+        /// Configuration file to read Tachi URL and API key from, when submitting
+        /// [default: takure.toml in TAKURE_DIR, or the current directory]
+        #[arg(long)]
+        config: Option<PathBuf>,
+        /// Directory in which files are written, when exporting
+        #[arg(long, default_value = ".")]
+        output_dir: PathBuf,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Session {
+    /// The most recent session
+    Last,
+    /// Every matching score
+    All,
+}
+
+fn parse_date(value: &str) -> Result<i64, String> {
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Ok(time.timestamp_millis());
+    }
+
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| format!("'{}' is not a YYYY-MM-DD or RFC 3339 date", value))?;
+    Local
+        .from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap_or_default())
+        .earliest()
+        .map(|time| time.timestamp_millis())
+        .ok_or_else(|| format!("'{}' is not a valid local date", value))
+}
+
+/// Title of the song of a score, or its code when the title is not known
+fn song(score: &Score) -> String {
+    score.title.clone().unwrap_or_else(|| score.mcode.clone())
+}
+
+fn flare(flare: Option<u8>) -> String {
+    batch_manual::flare_name(flare).unwrap_or_default().to_string()
+}
+
+fn history(cli: &Cli, database: &Database, filter: &Filter, titles: &Titles, limit: Option<usize>) -> Result<()> {
+    let mut scores = scores(database, filter, titles)?;
+    if let Some(limit) = limit {
+        scores.truncate(limit);
+    }
+
+    if cli.json {
+        return output::print_json(&scores);
+    }
+
+    let rows = scores
+        .iter()
+        .map(|score| {
+            vec![
+                output::format_time(score.time_achieved),
+                score.card.clone(),
+                score.playtype.clone(),
+                song(score),
+                score.difficulty.clone(),
+                score.score.to_string(),
+                score.ex_score.to_string(),
+                score.lamp.clone(),
+                flare(score.flare),
+                score.status.clone(),
+            ]
+        })
+        .collect::<Vec<_>>();
+    output::print_table(
+        &["Date", "Card", "Style", "Song", "Difficulty", "Score", "EX", "Lamp", "Flare", "Status"],
+        &rows,
+    );
+
+    Ok(())
+}
+
+fn pbs(cli: &Cli, database: &Database, filter: &Filter, titles: &Titles) -> Result<()> {
+    let mut scores = scores(database, filter, titles)?;
+    // Stable sort, so that the earliest play wins between equal scores
+    scores.reverse();
+    scores.sort_by_key(|score| std::cmp::Reverse(score.score));
+
+    let mut charts = HashSet::new();
+    let mut pbs = scores
+        .into_iter()
+        .filter(|score| {
+            charts.insert((
+                score.card.clone(),
+                score.playtype.clone(),
+                score.mcode.clone(),
+                score.difficulty.clone(),
+            ))
+        })
+        .collect::<Vec<_>>();
+    pbs.sort_by(|a, b| {
+        (&a.card, &a.playtype, &a.mcode, &a.difficulty).cmp(&(&b.card, &b.playtype, &b.mcode, &b.difficulty))
+    });
+
+    if cli.json {
+        return output::print_json(&pbs);
+    }
+
+    let rows = pbs
+        .iter()
+        .map(|score| {
+            vec![
+                score.card.clone(),
+                score.playtype.clone(),
+                song(score),
+                score.difficulty.clone(),
+                score.score.to_string(),
+                score.ex_score.to_string(),
+                score.lamp.clone(),
+                flare(score.flare),
+                output::format_time(score.time_achieved),
+            ]
+        })
+        .collect::<Vec<_>>();
+    output::print_table(
+        &["Card", "Style", "Song", "Difficulty", "Score", "EX", "Lamp", "Flare", "Date"],
+        &rows,
+    );
+
+    Ok(())
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Stats {
+    plays: usize,
+    first_play: Option<i64>,
+    last_play: Option<i64>,
+    clears: usize,
+    full_combos: usize,
+    average_score: u32,
+    total_ex_score: u64,
+    difficulties: BTreeMap<String, usize>,
+    statuses: BTreeMap<String, usize>,
+}
+
+impl Stats {
+    fn new(scores: &[Score]) -> Self {
+        let mut stats = Stats {
+            plays: scores.len(),
+            first_play: scores.iter().map(|score| score.time_achieved).min(),
+            last_play: scores.iter().map(|score| score.time_achieved).max(),
+            ..Default::default()
+        };
+
+        let mut total_score = 0u64;
+        for score in scores {
+            if score.lamp != "FAILED" {
+                stats.clears += 1;
+            }
+            if score.lamp.ends_with("FULL COMBO") {
+                stats.full_combos += 1;
+            }
+            total_score += score.score as u64;
+            stats.total_ex_score += score.ex_score as u64;
+            *stats.difficulties.entry(score.difficulty.clone()).or_default() += 1;
+            *stats.statuses.entry(score.status.clone()).or_default() += 1;
+        }
+        if !scores.is_empty() {
+            stats.average_score = (total_score / scores.len() as u64) as u32;
+        }
+
+        stats
+    }
+}
+
+fn stats(cli: &Cli, database: &Database, filter: &Filter, session: Session, gap: i64) -> Result<()> {
+    let mut scores = database.scores(filter)?;
+    if session == Session::Last {
+        // Scores are sorted from the most recent, the session ends at the first large gap
+        let gap = gap * 60 * 1000;
+        let end = scores
+            .windows(2)
+            .position(|pair| pair[0].time_achieved - pair[1].time_achieved > gap)
+            .map(|index| index + 1)
+            .unwrap_or(scores.len());
+        scores.truncate(end);
+    }
+
+    let stats = Stats::new(&scores);
+    if cli.json {
+        return output::print_json(&stats);
+    }
+
+    let optional_time = |time: Option<i64>| time.map(output::format_time).unwrap_or_default();
+    let mut rows = vec![
+        vec!["Plays".to_string(), stats.plays.to_string()],
+        vec!["First play".to_string(), optional_time(stats.first_play)],
+        vec!["Last play".to_string(), optional_time(stats.last_play)],
+        vec!["Clears".to_string(), stats.clears.to_string()],
+        vec!["Full combos".to_string(), stats.full_combos.to_string()],
+        vec!["Average score".to_string(), stats.average_score.to_string()],
+        vec!["Total EX score".to_string(), stats.total_ex_score.to_string()],
+    ];
+    for (difficulty, count) in &stats.difficulties {
+        rows.push(vec![format!("{} plays", difficulty), count.to_string()]);
+    }
+    for (status, count) in &stats.statuses {
+        rows.push(vec![format!("Scores {}", status), count.to_string()]);
+    }
+    output::print_table(&["Statistic", "Value"], &rows);
+
+    Ok(())
+}
+
+fn export(database: &Database, filter: &Filter, titles: &Titles, path: Option<&PathBuf>) -> Result<()> {
+    let mut scores = scores(database, filter, titles)?;
+    scores.reverse();
+
+    match path {
+        Some(path) => {
+            std::fs::write(path, serde_json::to_string_pretty(&scores)?)
+                .map_err(|err| anyhow::anyhow!("Could not write '{}': {:#}", path.display(), err))?;
+            eprintln!("Exported {} score(s) to '{}'", scores.len(), path.display());
+            Ok(())
+        }
+        None => output::print_json(&scores),
+    }
+}
+
+fn batch_manual(
+    database: &Path,
+    mut filter: Filter,
+    queue: Option<&PathBuf>,
+    output_dir: &Path,
+) -> Result<()> {
+    let scores = match queue {
+        Some(queue) => batch_manual::from_queue(queue, &filter)?,
+        None => {
+            if filter.statuses.is_empty() {
+                filter.statuses = ["submitted", "queued", "unsubmitted", "failed"]
+                    .iter()
+                    .map(|status| status.to_string())
+                    .collect();
+            }
+            let mut scores = Database::open(database)?.scores(&filter)?;
+            scores.reverse();
+            batch_manual::from_scores(&scores)
+        }
+    };
+
+    write_batch_manual(output_dir, scores)
+}
+
+fn write_batch_manual(output_dir: &Path, scores: BTreeMap<String, Vec<serde_json::Value>>) -> Result<()> {
+    if scores.is_empty() {
+        eprintln!("No score to export");
+        return Ok(());
+    }
+
+    std::fs::create_dir_all(output_dir)?;
+    for (playtype, scores) in scores {
+        let path = output_dir.join(format!("takure-batch-manual-{}.json", playtype));
+        let count = scores.len();
+        let file = batch_manual::build(&playtype, scores);
+        std::fs::write(&path, serde_json::to_string_pretty(&file)?)
+            .map_err(|err| anyhow::anyhow!("Could not write '{}': {:#}", path.display(), err))?;
+        eprintln!("Exported {} {} score(s) to '{}'", count, playtype, path.display());
+    }
+
+    Ok(())
+}
+
+/// Reads a string option of the `[tachi]` section of takure.toml
+fn tachi_option<'a>(config: &'a toml::Value, path: &Path, key: &str) -> Result<&'a str> {
+    config
+        .get("tachi")
+        .and_then(|tachi| tachi.get(key))
+        .and_then(toml::Value::as_str)
+        .ok_or_else(|| anyhow::anyhow!("'tachi.{}' is not set in '{}'", key, path.display()))
+}
+
+/// Submits recovered scores to the main Tachi instance, recording them like Takure does
+///
+/// Plays left pending by Takure or a previous backfill might have reached Tachi, they are looked
+/// up among the recent scores of the user rather than sent again.
+fn submit_to_tachi(
+    path: &Path,
+    confirmed: &fingerprint::Record,
+    recovered: &[backfill::Recovered],
+) -> Result<()> {
+    let config = std::fs::read_to_string(path)
+        .map_err(|err| anyhow::anyhow!("Could not read '{}': {:#}", path.display(), err))?
+        .parse::<toml::Value>()
+        .map_err(|err| anyhow::anyhow!("Could not parse '{}': {:#}", path.display(), err))?;
+    let base_url = url::Url::parse(tachi_option(&config, path, "base_url")?)
+        .map_err(|err| anyhow::anyhow!("Could not parse Tachi URL: {:#}", err))?;
+    let api_key = tachi_option(&config, path, "api_key")?;
+    let url = base_url
+        .join("/ir/direct-manual/import")
+        .map_err(|err| anyhow::anyhow!("Could not parse Tachi URL: {:#}", err))?;
+
+    let agent = ureq::builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .build();
+    let authorization = format!("Bearer {}", api_key);
+
+    let recent_scores = |path: &str| -> Result<Vec<serde_json::Value>> {
+        let url = base_url
+            .join(path)
+            .map_err(|err| anyhow::anyhow!("Could not parse Tachi URL: {:#}", err))?;
+        let response: serde_json::Value = agent
+            .get(url.as_str())
+            .set("Authorization", &authorization)
+            .call()?
+            .into_json()?;
+
+        Ok(response["body"]["scores"].as_array().cloned().unwrap_or_default())
+    };
+
+    // Recent scores of the user by game and play style, or none if they could not be read
+    let mut recent = HashMap::<String, Option<Vec<serde_json::Value>>>::new();
+    let mut submitted = 0;
+    let mut received = 0;
+    for entry in recovered {
+        let score = &entry.import.scores[0];
+        if confirmed.state(&score.fingerprint) == Some(fingerprint::State::Pending) {
+            let path = format!(
+                "/api/v1/users/me/games/{:?}/{:?}/scores/recent",
+                entry.import.meta.game, entry.import.meta.play_type
+            );
+            let scores = recent.entry(path).or_insert_with_key(|path| {
+                recent_scores(path)
+                    .map_err(|err| {
+                        eprintln!(
+                            "Could not get recent scores from Tachi, pending score(s) will not be sent again: {:#}",
+                            err
+                        )
+                    })
+                    .ok()
+            });
+            match scores {
+                Some(scores) if fingerprint::is_received(scores, score) => {
+                    confirmed.confirm([score.fingerprint.as_str()])?;
+                    received += 1;
+                    continue;
+                }
+                Some(_) => {}
+                None => continue,
+            }
+        }
+
+        confirmed.mark_pending([score.fingerprint.as_str()])?;
+        let result = agent
+            .post(url.as_str())
+            .set("Authorization", &authorization)
+            .send_json(&entry.import);
+        match result {
+            Ok(_) => {
+                confirmed.confirm([score.fingerprint.as_str()])?;
+                submitted += 1;
+            }
+            Err(err) => eprintln!(
+                "Could not submit score on song {} ({:?}) achieved {}: {:#}",
+                score.identifier,
+                score.difficulty,
+                output::format_time(score.time_achieved as i64),
+                err
+            ),
+        }
+    }
+    eprintln!(
+        "Submitted {} of {} score(s), {} were already received by Tachi",
+        submitted,
+        recovered.len(),
+        received
+    );
+
+    Ok(())
+}
+
+fn backfill(logs: &[PathBuf], submit: bool, config: &Path, output_dir: &Path) -> Result<()> {
+    let mut recovered = Vec::new();
+    for log in logs {
+        let scores = backfill::parse_log(log)?;
+        eprintln!("Found {} score(s) in '{}'", scores.len(), log.display());
+        recovered.extend(scores);
+    }
+
+    let mut recovered = backfill::dedup(recovered);
+    // Only the main Tachi instance is supported
+    let confirmed = fingerprint::Record::new(takure_file(fingerprint::CONFIRMED_PATH).to_string_lossy());
+    backfill::match_recorded(&mut recovered, |fingerprint| confirmed.state(fingerprint).is_some());
+    let count = recovered.len();
+    let recovered = recovered
+        .into_iter()
+        .filter(|entry| {
+            confirmed.state(&entry.import.scores[0].fingerprint) != Some(fingerprint::State::Confirmed)
+        })
+        .collect::<Vec<_>>();
+    eprintln!(
+        "Recovered {} unique score(s), {} of them were already submitted",
+        count,
+        count - recovered.len()
+    );
+
+    if submit {
+        return submit_to_tachi(config, &confirmed, &recovered);
+    }
+
+    let mut scores = BTreeMap::<String, Vec<serde_json::Value>>::new();
+    for entry in &recovered {
+        let playtype = serde_json::to_value(&entry.import.meta.play_type)?
+            .as_str()
+            .unwrap_or("SP")
+            .to_string();
+        for score in &entry.import.scores {
+            scores.entry(playtype.clone()).or_default().push(serde_json::to_value(score)?);
+        }
+    }
+    write_batch_manual(output_dir, scores)
+}
+
+/// Returns the path of a file written by Takure, in the folder set by TAKURE_DIR like Takure does,
+/// or in the current directory
+fn takure_file(name: &str) -> PathBuf {
+    match std::env::var_os("TAKURE_DIR").filter(|value| !value.is_empty()) {
+        Some(directory) => PathBuf::from(directory).join(name),
+        None => PathBuf::from(name),
+    }
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let database = cli.database.clone().unwrap_or_else(|| takure_file("takure.db"));
+    let open = || Database::open(&database);
+    let titles = read_titles(cli.musicdb.as_deref())?;
+
+    match &cli.command {
+        Command::History { filter, limit } => {
+            history(&cli, &open()?, &filter.to_filter(&titles), &titles, *limit)
+        }
+        Command::Pbs { filter } => pbs(&cli, &open()?, &filter.to_filter(&titles), &titles),
+        Command::Stats { filter, session, gap } => {
+            stats(&cli, &open()?, &filter.to_filter(&titles), *session, *gap)
+        }
+        Command::Export { filter, output } => {
+            export(&open()?, &filter.to_filter(&titles), &titles, output.as_ref())
+        }
+        Command::BatchManual { filter, queue, output_dir } => {
+            batch_manual(&database, filter.to_filter(&titles), queue.as_ref(), output_dir)
+        }
+        Command::Backfill { logs, submit, config, output_dir } => {
+            let config = config.clone().unwrap_or_else(|| takure_file("takure.toml"));
+            backfill(logs, *submit, &config, output_dir)
+        }
+    }
+}
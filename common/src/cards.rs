@@ -0,0 +1,285 @@
+use anyhow::Result;
+use des::cipher::generic_array::GenericArray;
+use des::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use des::TdesEde3;
+use std::fmt;
+
+// Every character of the key is shifted left by one bit before use
+const KEY: &[u8; 24] = b"?I'llB2c.YouXXXeMeHaYpy!";
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKLMNPRSTUWXYZ";
+
+fn cipher() -> TdesEde3 {
+    let mut key = [0u8; 24];
+    for (dst, src) in key.iter_mut().zip(KEY.iter()) {
+        *dst = src << 1;
+    }
+
+    TdesEde3::new(GenericArray::from_slice(&key))
+}
+
+fn checksum(groups: &[u8; 16]) -> u8 {
+    let mut checksum = groups[..15]
+        .iter()
+        .enumerate()
+        .map(|(i, group)| (i as u32 % 3 + 1) * *group as u32)
+        .sum::<u32>();
+    while checksum >= 0x20 {
+        checksum = (checksum & 0x1F) + (checksum >> 5);
+    }
+
+    checksum as u8
+}
+
+fn parse_uid(uid: &str) -> Option<[u8; 8]> {
+    if uid.len() != 16 || !uid.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let mut bytes = [0u8; 8];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&uid[i * 2..i * 2 + 2], 16).ok()?;
+    }
+
+    Some(bytes)
+}
+
+/// Converts a raw card UID (E004 or 012E format) to the card number printed on the e-amusement pass
+pub fn to_konami_id(uid: &str) -> Result<String> {
+    let bytes = parse_uid(uid).ok_or(anyhow::anyhow!("Invalid card UID '{}'", uid))?;
+    let card_type = if bytes[0] == 0xE0 { 1 } else { 2 };
+
+    let mut block = bytes;
+    block.reverse();
+    let mut block = GenericArray::from(block);
+    cipher().encrypt_block(&mut block);
+
+    let mut bits = [0u8; 65];
+    for (i, bit) in bits.iter_mut().take(64).enumerate() {
+        *bit = (block[i >> 3] >> (!i & 7)) & 1;
+    }
+
+    let mut groups = [0u8; 16];
+    for (i, group) in groups.iter_mut().take(13).enumerate() {
+        *group = bits[i * 5..i * 5 + 5]
+            .iter()
+            .fold(0, |acc, bit| (acc << 1) | bit);
+    }
+
+    groups[13] = 1;
+    groups[0] ^= card_type;
+    for i in 1..14 {
+        groups[i] ^= groups[i - 1];
+    }
+    groups[14] = card_type;
+    groups[15] = checksum(&groups);
+
+    Ok(groups
+        .iter()
+        .map(|group| ALPHABET[*group as usize] as char)
+        .collect())
+}
+
+/// Converts a card number printed on the e-amusement pass to its raw card UID
+pub fn to_uid(konami_id: &str) -> Result<String> {
+    let invalid = || anyhow::anyhow!("Invalid card number '{}'", konami_id);
+    if konami_id.len() != 16 {
+        return Err(invalid());
+    }
+
+    let mut groups = [0u8; 16];
+    for (group, c) in groups.iter_mut().zip(konami_id.bytes()) {
+        let c = match c {
+            b'I' => b'1',
+            b'O' => b'0',
+            c => c,
+        };
+        *group = ALPHABET.iter().position(|a| *a == c).ok_or_else(invalid)? as u8;
+    }
+
+    let card_type = groups[14];
+    if (card_type != 1 && card_type != 2) || groups[15] != checksum(&groups) {
+        return Err(invalid());
+    }
+
+    for i in (1..14).rev() {
+        groups[i] ^= groups[i - 1];
+    }
+    groups[0] ^= card_type;
+
+    let mut block = GenericArray::from([0u8; 8]);
+    for i in 0..64 {
+        let bit = (groups[i / 5] >> (4 - i % 5)) & 1;
+        block[i >> 3] |= bit << (!i & 7);
+    }
+    cipher().decrypt_block(&mut block);
+    block.reverse();
+
+    if (card_type == 1) != (block[0] == 0xE0) {
+        return Err(invalid());
+    }
+
+    Ok(block.iter().map(|byte| format!("{:02X}", byte)).collect())
+}
+
+/// Normalizes a card UID or printed card number into an uppercase card UID
+///
+/// Case and separators (spaces, dashes, dots...) are ignored.
+pub fn normalize(card: &str) -> Option<String> {
+    let card = card
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>()
+        .to_ascii_uppercase();
+
+    if (card.starts_with("E004") || card.starts_with("012E")) && parse_uid(&card).is_some() {
+        return Some(card);
+    }
+
+    to_uid(&card).ok()
+}
+
+/// Returns true if both entries refer to the same card, whatever their format
+///
+/// Entries which are neither a card UID nor a printed card number are compared as they are,
+/// ignoring case.
+pub fn is_same_card(a: &str, b: &str) -> bool {
+    match (normalize(a), normalize(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => a.trim().eq_ignore_ascii_case(b.trim()),
+    }
+}
+
+/// Displays a card UID along with its printed card number
+pub struct Card<'a>(pub &'a str);
+
+impl fmt::Display for Card<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match to_konami_id(self.0) {
+            Ok(konami_id) => write!(
+                f,
+                "{} ({}-{}-{}-{})",
+                self.0,
+                &konami_id[0..4],
+                &konami_id[4..8],
+                &konami_id[8..12],
+                &konami_id[12..16]
+            ),
+            Err(_) => write!(f, "{}", self.0),
+        }
+    }
+}
+
+/// Matches a string against a pattern where '*' matches any sequence and '?' any single character
+fn wildcard_match(pattern: &[u8], value: &[u8]) -> bool {
+    match (pattern.first(), value.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            wildcard_match(&pattern[1..], value)
+                || (!value.is_empty() && wildcard_match(pattern, &value[1..]))
+        }
+        (Some(b'?'), Some(_)) => wildcard_match(&pattern[1..], &value[1..]),
+        (Some(p), Some(v)) if p == v => wildcard_match(&pattern[1..], &value[1..]),
+        _ => false,
+    }
+}
+
+fn strip_separators(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '*' || *c == '?')
+        .collect::<String>()
+        .to_ascii_uppercase()
+}
+
+/// Checks a card list entry against a card UID and the refid of the player
+///
+/// Entries starting with `refid:` match the refid, other entries match the card, either as its
+/// UID or its printed card number. Entries containing `*` or `?` are matched as wildcards.
+pub fn matches_entry(entry: &str, card: &str, ref_id: &str) -> bool {
+    if let Some(pattern) = entry.strip_prefix("refid:") {
+        let pattern = pattern.trim().to_ascii_uppercase();
+        return wildcard_match(pattern.as_bytes(), ref_id.to_ascii_uppercase().as_bytes());
+    }
+
+    if !entry.contains(['*', '?']) {
+        return is_same_card(entry, card);
+    }
+
+    let pattern = strip_separators(entry);
+    let card = card.to_ascii_uppercase();
+    wildcard_match(pattern.as_bytes(), card.as_bytes())
+        || to_konami_id(&card)
+            .map(|konami_id| wildcard_match(pattern.as_bytes(), konami_id.as_bytes()))
+            .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UID: &str = "E004010027A5FC68";
+    const KONAMI_ID: &str = "S6E523E30ZK7ML1P";
+
+    #[test]
+    fn converts_known_card() {
+        assert_eq!(to_konami_id(UID).unwrap(), KONAMI_ID);
+        assert_eq!(to_uid(KONAMI_ID).unwrap(), UID);
+    }
+
+    #[test]
+    fn round_trips() {
+        for uid in ["E004010027A5FC68", "E004000000000001", "012E0000DEADBEEF", "012E3456789ABCDE"] {
+            let konami_id = to_konami_id(uid).unwrap();
+            assert_eq!(to_uid(&konami_id).unwrap(), uid);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_card_numbers() {
+        // Last character is the checksum
+        assert!(to_uid("S6E523E30ZK7ML1A").is_err());
+        assert!(to_uid("S6E523E30ZK7ML1").is_err());
+        assert!(to_uid("S6E523E30ZK7ML1!").is_err());
+        assert!(to_konami_id("E004010027A5FC6").is_err());
+        assert!(to_konami_id("E004010027A5FCZZ").is_err());
+    }
+
+    #[test]
+    fn normalizes_case_and_separators() {
+        assert_eq!(normalize("e004 0100 27a5 fc68").as_deref(), Some(UID));
+        assert_eq!(normalize("s6e5-23e3-0zk7-ml1p").as_deref(), Some(UID));
+        // 'I' and 'O' are read as '1' and '0'
+        assert_eq!(normalize("S6E5-23E3-OZK7-MLIP").as_deref(), Some(UID));
+        assert_eq!(normalize("not a card"), None);
+    }
+
+    #[test]
+    fn compares_cards() {
+        assert!(is_same_card(UID, "S6E5-23E3-0ZK7-ML1P"));
+        assert!(is_same_card("e004010027a5fc68", KONAMI_ID));
+        assert!(!is_same_card(UID, "E004010027A5FC69"));
+        // Entries which cannot be normalized still match exactly
+        assert!(is_same_card("ABC123", "abc123"));
+        assert!(!is_same_card("ABC123", "ABC124"));
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(wildcard_match(b"E004*", b"E004010027A5FC68"));
+        assert!(wildcard_match(b"*FC68", b"E004010027A5FC68"));
+        assert!(wildcard_match(b"E00?01*68", b"E004010027A5FC68"));
+        assert!(wildcard_match(b"*", b""));
+        assert!(!wildcard_match(b"?", b""));
+        assert!(!wildcard_match(b"E004", b"E004010027A5FC68"));
+        assert!(!wildcard_match(b"012E*", b"E004010027A5FC68"));
+    }
+
+    #[test]
+    fn matches_entries() {
+        assert!(matches_entry("S6E5-23E3-0ZK7-ML1P", UID, ""));
+        assert!(matches_entry("s6e5-*", UID, ""));
+        assert!(matches_entry("e004 01*", UID, ""));
+        assert!(matches_entry("refid:abc*", UID, "ABCDEF"));
+        assert!(!matches_entry("refid:abc*", UID, "DEF"));
+    }
+}
@@ -0,0 +1,209 @@
+use anyhow::Result;
+use crate::types::tachi::ImportScore;
+use log::{error, info};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Record of the plays confirmed by the main Tachi instance
+pub const CONFIRMED_PATH: &str = "takure.submitted";
+
+/// Plays recorded before this many days are forgotten, long after they could be submitted again
+const RETENTION_DAYS: u64 = 180;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// The play is being submitted, Tachi might have received it even if the request failed
+    Pending,
+    /// Tachi confirmed receiving the play
+    Confirmed,
+}
+
+impl State {
+    fn as_str(self) -> &'static str {
+        match self {
+            State::Pending => "pending",
+            State::Confirmed => "confirmed",
+        }
+    }
+}
+
+/// Plays sent to a Tachi instance, stored one fingerprint per line with their state and the time
+/// it was recorded, and loaded lazily
+pub struct Record {
+    path: String,
+    plays: Mutex<Option<HashMap<String, (State, u64)>>>,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Parses a line of the record, lines written by older versions only having the fingerprint of a
+/// confirmed play
+fn parse_line(line: &str, now: u64) -> Option<(String, State, u64)> {
+    let mut parts = line.split_whitespace();
+    let fingerprint = parts.next()?.to_string();
+    let state = match parts.next() {
+        Some("pending") => State::Pending,
+        _ => State::Confirmed,
+    };
+    let time = parts.next().and_then(|time| time.parse().ok()).unwrap_or(now);
+
+    Some((fingerprint, state, time))
+}
+
+/// Computes a stable identifier of a play, which does not depend on when it is submitted
+pub fn compute(card: &str, score: &ImportScore) -> String {
+    let judgements = &score.judgements;
+    let data = format!(
+        "{}|{}|{:?}|{}|{}|{}|{}|{}|{}|{}|{}",
+        card.to_ascii_uppercase(),
+        score.identifier,
+        score.difficulty,
+        score.score,
+        judgements.marvelous,
+        judgements.perfect,
+        judgements.great,
+        judgements.good,
+        judgements.miss,
+        judgements.ok,
+        score.time_achieved
+    );
+
+    Sha256::digest(data.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Returns true if the recent scores of the user on Tachi contain this score, to find out whether
+/// a pending play was received
+pub fn is_received(recent: &[serde_json::Value], score: &ImportScore) -> bool {
+    recent.iter().any(|received| {
+        received["timeAchieved"].as_u64().map(u128::from) == Some(score.time_achieved)
+            && received["scoreData"]["score"].as_u64() == Some(u64::from(score.score))
+    })
+}
+
+impl Record {
+    pub fn new(path: impl Into<String>) -> Self {
+        Record {
+            path: path.into(),
+            plays: Mutex::new(None),
+        }
+    }
+
+    /// Reads the record, rewriting it without the plays older than the retention period
+    fn load(&self) -> Result<HashMap<String, (State, u64)>> {
+        if !Path::new(&self.path).exists() {
+            return Ok(HashMap::new());
+        }
+
+        let file = File::open(&self.path)
+            .map_err(|err| anyhow::anyhow!("Could not open submitted scores record: {:#}", err))?;
+        let now = now();
+        let mut plays = HashMap::new();
+        let mut lines = 0;
+        for line in BufReader::new(file).lines() {
+            let line = line
+                .map_err(|err| anyhow::anyhow!("Could not read submitted scores record: {:#}", err))?;
+            // Later lines replace earlier ones, a pending play being confirmed afterwards
+            if let Some((fingerprint, state, time)) = parse_line(&line, now) {
+                lines += 1;
+                plays.insert(fingerprint, (state, time));
+            }
+        }
+
+        let before = plays.len();
+        plays.retain(|_, (_, time)| now.saturating_sub(*time) < RETENTION_DAYS * 24 * 60 * 60);
+        if plays.len() != lines {
+            self.rewrite(&plays)?;
+            if plays.len() != before {
+                info!(
+                    "Forgot {} submitted score(s) older than {} days",
+                    before - plays.len(),
+                    RETENTION_DAYS
+                );
+            }
+        }
+
+        Ok(plays)
+    }
+
+    fn rewrite(&self, plays: &HashMap<String, (State, u64)>) -> Result<()> {
+        let temporary = format!("{}.tmp", self.path);
+        let write = || -> std::io::Result<()> {
+            let mut file = File::create(&temporary)?;
+            for (fingerprint, (state, time)) in plays {
+                writeln!(file, "{} {} {}", fingerprint, state.as_str(), time)?;
+            }
+            file.sync_all()?;
+            drop(file);
+            std::fs::rename(&temporary, &self.path)
+        };
+
+        write().map_err(|err| anyhow::anyhow!("Could not rewrite submitted scores record: {:#}", err))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<HashMap<String, (State, u64)>>> {
+        let mut guard = self.plays.lock().unwrap_or_else(|err| {
+            error!("Submitted scores Mutex is poisoned: {:#}", err);
+            err.into_inner()
+        });
+        if guard.is_none() {
+            match self.load() {
+                Ok(plays) => *guard = Some(plays),
+                Err(err) => error!("{:#}", err),
+            }
+        }
+
+        guard
+    }
+
+    /// Returns the state of the play with this fingerprint, if it was ever sent
+    pub fn state(&self, fingerprint: &str) -> Option<State> {
+        self.lock()
+            .as_ref()
+            .and_then(|plays| plays.get(fingerprint))
+            .map(|(state, _)| *state)
+    }
+
+    fn record<'a>(&self, state: State, fingerprints: impl IntoIterator<Item = &'a str>) -> Result<()> {
+        let mut guard = self.lock();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|err| anyhow::anyhow!("Could not open submitted scores record: {:#}", err))?;
+
+        let now = now();
+        for fingerprint in fingerprints {
+            writeln!(file, "{} {} {}", fingerprint, state.as_str(), now)
+                .map_err(|err| anyhow::anyhow!("Could not write submitted scores record: {:#}", err))?;
+            if let Some(plays) = guard.as_mut() {
+                plays.insert(fingerprint.to_string(), (state, now));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records that the plays with these fingerprints are about to be sent, so that they are
+    /// checked on Tachi before being sent again if the outcome of the request is unknown
+    pub fn mark_pending<'a>(&self, fingerprints: impl IntoIterator<Item = &'a str>) -> Result<()> {
+        self.record(State::Pending, fingerprints)
+    }
+
+    /// Records that Tachi confirmed receiving the plays with these fingerprints
+    pub fn confirm<'a>(&self, fingerprints: impl IntoIterator<Item = &'a str>) -> Result<()> {
+        self.record(State::Confirmed, fingerprints)
+    }
+}
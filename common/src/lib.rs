@@ -0,0 +1,5 @@
+// Shared by the hook and takure-cli, so that scores are built and fingerprinted the same way
+pub mod cards;
+pub mod fingerprint;
+pub mod music;
+pub mod types;
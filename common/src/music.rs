@@ -0,0 +1,35 @@
+use std::collections::HashMap;
+
+fn element<'a>(music: &'a str, name: &str) -> Option<&'a str> {
+    // Elements may have attributes, and other element names may start with the same prefix
+    let start = [format!("<{}>", name), format!("<{} ", name)]
+        .iter()
+        .filter_map(|tag| music.find(tag.as_str()))
+        .min()?;
+    let start = start + music[start..].find('>')? + 1;
+    let end = start + music[start..].find(&format!("</{}>", name))?;
+
+    Some(music[start..end].trim())
+}
+
+fn unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Reads the title of every song from the game music database, by song code
+pub fn parse(content: &str) -> HashMap<String, String> {
+    content
+        .split("<music>")
+        .skip(1)
+        .filter_map(|music| {
+            let mcode = element(music, "mcode")?;
+            let title = element(music, "title")?;
+            Some((mcode.to_string(), unescape(title)))
+        })
+        .collect()
+}
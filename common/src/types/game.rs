@@ -0,0 +1,184 @@
+use crate::types::tachi::{Difficulty, Flare, HitMeta, ImportMeta, ImportScore, Judgements, Optional, Playtype, TachiLamp};
+use serde::{Deserialize, Serialize};
+
+// DDR A3
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Property2 {
+    pub call: CallStruct2,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CallStruct2 {
+    pub playerdata_2: PlayerData2,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayerData2 {
+    pub data: PlayerData2Data,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayerData2Data {
+    pub mode: String,
+    #[serde(rename = "refid")]
+    pub ref_id: String,
+    #[serde(default)]
+    pub isgameover: bool,
+    #[serde(default)]
+    pub note: Vec<Note>,
+}
+
+impl PlayerData2Data {
+    /// Returns the note of the last played stage, if any
+    pub fn highest_stage(&self) -> Option<&Note> {
+        self.note
+            .iter()
+            .filter(|n| n.stagenum != 0)
+            .max_by_key(|n| n.stagenum)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Note {
+    pub stagenum: u8,
+    pub mcode: u32,
+    pub notetype: u8,
+    pub clearkind: u8,
+    pub score: u32,
+    #[serde(rename = "exscore")]
+    pub ex_score: u32,
+    pub maxcombo: u32,
+    pub fastcount: u32,
+    pub slowcount: u32,
+    pub judge_marvelous: u32,
+    pub judge_perfect: u32,
+    pub judge_great: u32,
+    pub judge_good: u32,
+    pub judge_miss: u32,
+    pub judge_ok: u32,
+    pub playstyle: u8,
+}
+
+impl Note {
+    pub fn to_import(&self, time_achieved: u128) -> anyhow::Result<(ImportScore, ImportMeta)> {
+        let import_score = ImportScore {
+            score: self.score,
+            lamp: TachiLamp::from(self.clearkind),
+            match_type: "inGameID".to_string(),
+            identifier: self.mcode.to_string(),
+            difficulty: Difficulty::try_from(self.notetype)?,
+            time_achieved,
+            judgements: Judgements {
+                marvelous: self.judge_marvelous,
+                perfect: self.judge_perfect,
+                great: self.judge_great,
+                good: self.judge_good,
+                miss: self.judge_miss,
+                ok: self.judge_ok,
+            },
+            hit_meta: HitMeta {
+                fast: self.fastcount,
+                slow: self.slowcount,
+                max_combo: self.maxcombo,
+                ex_score: self.ex_score,
+            },
+            optional: Optional::default(),
+            fingerprint: String::new(),
+        };
+
+        let import_meta = ImportMeta {
+            game: "ddr".to_string(),
+            play_type: Playtype::try_from(self.playstyle)?,
+            service: "Takure".to_string(),
+        };
+
+        Ok((import_score, import_meta))
+    }
+}
+
+// DDR WORLD
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Property3 {
+    pub call: CallStruct3,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CallStruct3 {
+    pub playdata_3: PlayData3,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayData3 {
+    pub data: PlayData3Data,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayData3Data {
+    #[serde(rename = "refid")]
+    pub ref_id: String,
+    pub savekind: u8,
+    pub result: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Result {
+    pub stagenum: u8,
+    pub mcode: u32,
+    pub difficulty: u8,
+    pub clearkind: u8,
+    pub score: u32,
+    #[serde(rename = "exscore")]
+    pub ex_score: u32,
+    pub maxcombo: u32,
+    pub fastcount: u32,
+    pub slowcount: u32,
+    pub judge_marv: u32,
+    pub judge_perf: u32,
+    pub judge_great: u32,
+    pub judge_good: u32,
+    pub judge_miss: u32,
+    pub judge_ok: u32,
+    pub style: u8,
+    pub flare_force: u8,
+}
+
+impl Result {
+    pub fn to_import(&self, time_achieved: u128) -> anyhow::Result<(ImportScore, ImportMeta)> {
+        let import_score = ImportScore {
+            score: self.score,
+            lamp: TachiLamp::from(self.clearkind),
+            match_type: "inGameID".to_string(),
+            identifier: self.mcode.to_string(),
+            difficulty: Difficulty::try_from(self.difficulty)?,
+            time_achieved,
+            judgements: Judgements {
+                marvelous: self.judge_marv,
+                perfect: self.judge_perf,
+                great: self.judge_great,
+                good: self.judge_good,
+                miss: self.judge_miss,
+                ok: self.judge_ok,
+            },
+            hit_meta: HitMeta {
+                fast: self.fastcount,
+                slow: self.slowcount,
+                max_combo: self.maxcombo,
+                ex_score: self.ex_score,
+            },
+            optional: if self.flare_force == 0 {
+                Optional::default()
+            } else {
+                Optional { flare: Flare::from(self.flare_force) }
+            },
+            fingerprint: String::new(),
+        };
+
+        let import_meta = ImportMeta {
+            game: "ddr".to_string(),
+            play_type: Playtype::try_from(self.style)?,
+            service: "Takure".to_string(),
+        };
+
+        Ok((import_score, import_meta))
+    }
+}
@@ -32,12 +32,14 @@ pub enum Playtype {
     DP,
 }
 
-impl From<u8> for Playtype {
-    fn from(value: u8) -> Self {
+impl TryFrom<u8> for Playtype {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
-            0 | 2 => Playtype::SP,
-            1 => Playtype::DP,
-            _ => unreachable!(),
+            0 | 2 => Ok(Playtype::SP),
+            1 => Ok(Playtype::DP),
+            _ => Err(anyhow::anyhow!("Unknown play style {}", value)),
         }
     }
 }
@@ -55,8 +57,11 @@ pub struct ImportScore {
     pub judgements: Judgements,
     #[serde(rename = "hitMeta")]
     pub hit_meta: HitMeta,
-    #[serde(skip_serializing_if = "Optional::is_default")]
+    #[serde(default, skip_serializing_if = "Optional::is_default")]
     pub optional: Optional,
+    // Not part of Tachi schema, see fingerprint::compute
+    #[serde(skip)]
+    pub fingerprint: String,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, FromPrimitive, Serialize, Deserialize)]
@@ -96,15 +101,17 @@ pub enum Difficulty {
     Challenge,
 }
 
-impl From<u8> for Difficulty {
-    fn from(value: u8) -> Self {
+impl TryFrom<u8> for Difficulty {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
-            0 => Difficulty::Beginner,
-            1 | 5 => Difficulty::Basic,
-            2 | 6 => Difficulty::Difficult,
-            3 | 7 => Difficulty::Expert,
-            4 | 8 => Difficulty::Challenge,
-            _ => unreachable!(),
+            0 => Ok(Difficulty::Beginner),
+            1 | 5 => Ok(Difficulty::Basic),
+            2 | 6 => Ok(Difficulty::Difficult),
+            3 | 7 => Ok(Difficulty::Expert),
+            4 | 8 => Ok(Difficulty::Challenge),
+            _ => Err(anyhow::anyhow!("Unknown difficulty {}", value)),
         }
     }
 }
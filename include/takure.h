@@ -0,0 +1,69 @@
+/*
+ * C ABI exported by takure.dll, for other modules injected in the game to receive card scans,
+ * game ends and stage results.
+ *
+ * Callbacks run on a Takure thread, one event at a time, and should return quickly. Strings are
+ * UTF-8, nul-terminated and only valid during the call.
+ *
+ * Fields are only ever appended to takure_event, check version or size before reading fields
+ * newer than TAKURE_ABI_VERSION below.
+ */
+
+#ifndef TAKURE_H
+#define TAKURE_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define TAKURE_ABI_VERSION 1
+
+#define TAKURE_EVENT_CARD_IN 1
+#define TAKURE_EVENT_CARD_OUT 2
+#define TAKURE_EVENT_SCORE 3
+
+typedef struct takure_event {
+    uint32_t version;
+    uint32_t size;
+    uint32_t kind;
+    const char *card;
+    const char *konami_id;
+    /* Score events only, zero or null for card events */
+    const char *title;
+    const char *mcode;
+    const char *playtype;
+    const char *difficulty;
+    const char *lamp;
+    const char *flare; /* null when not set */
+    uint32_t score;
+    uint32_t ex_score;
+    uint32_t max_combo;
+    uint32_t marvelous;
+    uint32_t perfect;
+    uint32_t great;
+    uint32_t good;
+    uint32_t ok;
+    uint32_t miss;
+    uint32_t fast;
+    uint32_t slow;
+    const char *skip_reason; /* null when the score was not skipped */
+} takure_event;
+
+typedef void (*takure_callback)(const takure_event *event, void *user_data);
+
+/* Version of the ABI implemented by the loaded takure.dll */
+uint32_t takure_abi_version(void);
+
+/* Returns a handle to unregister the callback, or -1 if it is null */
+int32_t takure_register_callback(takure_callback callback, void *user_data);
+
+/* Returns 0 on success, or -1 if the handle is unknown */
+int32_t takure_unregister_callback(int32_t handle);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,33 @@
+use crate::configuration::CardsConfiguration;
+pub use takure_common::cards::{matches_entry, normalize, to_konami_id, Card};
+
+pub enum Access<'a> {
+    Allowed,
+    Blacklisted(&'a str),
+    NotWhitelisted,
+}
+
+impl CardsConfiguration {
+    /// Checks whether scores of the given card and refid should be submitted, the blacklist
+    /// taking precedence over the whitelist
+    pub fn access(&self, card: &str, ref_id: &str) -> Access<'_> {
+        if let Some(entry) = self
+            .blacklist
+            .iter()
+            .find(|entry| matches_entry(entry, card, ref_id))
+        {
+            return Access::Blacklisted(entry);
+        }
+
+        if !self.whitelist.is_empty()
+            && !self
+                .whitelist
+                .iter()
+                .any(|entry| matches_entry(entry, card, ref_id))
+        {
+            return Access::NotWhitelisted;
+        }
+
+        Access::Allowed
+    }
+}
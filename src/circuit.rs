@@ -0,0 +1,118 @@
+use crate::tachi::Instance;
+use crate::{helpers, queue, CONFIGURATION};
+use log::{error, info, warn};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Requests go through
+    Closed,
+    /// Requests are not attempted until the cool-down is over
+    Open(Instant),
+    /// The cool-down is over and the status endpoint is being probed
+    HalfOpen,
+}
+
+struct Circuit {
+    state: State,
+    failures: u32,
+}
+
+/// Circuit breaker of a Tachi instance
+pub struct Breaker {
+    circuit: Mutex<Circuit>,
+}
+
+impl Breaker {
+    pub fn new() -> Self {
+        Breaker {
+            circuit: Mutex::new(Circuit {
+                state: State::Closed,
+                failures: 0,
+            }),
+        }
+    }
+}
+
+fn lock(instance: &Instance) -> MutexGuard<'_, Circuit> {
+    instance.breaker.circuit.lock().unwrap_or_else(|err| {
+        error!("Circuit breaker Mutex is poisoned: {:#}", err);
+        err.into_inner()
+    })
+}
+
+fn open(instance: &Instance, circuit: &mut Circuit) {
+    let cooldown = Duration::from_secs(CONFIGURATION.get().general.breaker_cooldown);
+    circuit.state = State::Open(Instant::now() + cooldown);
+    warn!(
+        "Circuit of {} opened after {} consecutive failure(s), scores will be queued for the next {}s",
+        instance,
+        circuit.failures,
+        cooldown.as_secs()
+    );
+}
+
+/// Returns true if a request to Tachi should be attempted
+///
+/// Once the cool-down is over, the status endpoint is probed in the background and requests are
+/// not attempted until the probe succeeds.
+pub fn allow_request(instance: &'static Instance) -> bool {
+    let mut circuit = lock(instance);
+    match circuit.state {
+        State::Closed => true,
+        State::Open(until) if Instant::now() < until => false,
+        State::Open(_) => {
+            circuit.state = State::HalfOpen;
+            info!("Circuit of {} half-opened, probing Tachi API", instance);
+            std::thread::spawn(move || probe(instance));
+            false
+        }
+        State::HalfOpen => false,
+    }
+}
+
+pub fn is_open(instance: &Instance) -> bool {
+    !matches!(lock(instance).state, State::Closed)
+}
+
+pub fn record_success(instance: &Instance) {
+    let mut circuit = lock(instance);
+    circuit.failures = 0;
+    if circuit.state != State::Closed {
+        circuit.state = State::Closed;
+        info!("Circuit of {} closed, scores will be submitted live again", instance);
+    }
+}
+
+/// Records a transient failure, opening the circuit once the threshold is reached
+pub fn record_failure(instance: &Instance) {
+    let mut circuit = lock(instance);
+    circuit.failures += 1;
+    match circuit.state {
+        State::Closed if circuit.failures >= CONFIGURATION.get().general.breaker_threshold.max(1) => {
+            open(instance, &mut circuit)
+        }
+        State::HalfOpen => open(instance, &mut circuit),
+        _ => {}
+    }
+}
+
+fn probe(instance: &'static Instance) {
+    match helpers::request_tachi::<(), serde_json::Value>(instance, "GET", &instance.status_url, None) {
+        Ok(_) => {
+            record_success(instance);
+            if let Err(err) = queue::flush(instance) {
+                error!("{:#}", err);
+            }
+        }
+        Err(err) => {
+            error!("{:#}", err);
+            // Permanent errors are not recorded as failures, but the circuit should not stay half-opened
+            let mut circuit = lock(instance);
+            if circuit.state == State::HalfOpen {
+                open(instance, &mut circuit);
+            }
+        }
+    }
+}
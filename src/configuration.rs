@@ -1,26 +1,100 @@
+use crate::types::tachi::{Difficulty, Playtype, TachiLamp};
 use anyhow::Result;
+use crate::{migration, paths, validation};
+use log::{error, warn};
 use serde::{Deserialize, Serialize};
 use std::fs::File;
 use std::io::Write;
 use std::path::Path;
+use std::sync::{Arc, RwLock};
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Configuration {
+    /// Version of the file, see migration::VERSION
+    #[serde(default)]
+    pub version: i64,
     pub general: GeneralConfiguration,
     pub cards: CardsConfiguration,
     pub tachi: TachiConfiguration,
+    #[serde(default)]
+    pub history: HistoryConfiguration,
+    #[serde(default)]
+    pub rules: Vec<RuleConfiguration>,
+    #[serde(default)]
+    pub sinks: Vec<SinkConfiguration>,
+    #[serde(default)]
+    pub overlay: OverlayConfiguration,
+    #[serde(default)]
+    pub now_playing: NowPlayingConfiguration,
+    #[serde(default)]
+    pub mqtt: MqttConfiguration,
+    #[serde(default)]
+    pub scripts: ScriptsConfiguration,
 }
 
+/// Configuration in use, replaced when takure.toml changes
+///
+/// Readers get a snapshot, so that a reload never changes the configuration in the middle of
+/// processing a score.
+pub struct Shared {
+    current: RwLock<Arc<Configuration>>,
+}
+
+impl Shared {
+    pub fn new(configuration: Configuration) -> Self {
+        Shared {
+            current: RwLock::new(Arc::new(configuration)),
+        }
+    }
+
+    pub fn get(&self) -> Arc<Configuration> {
+        self.current
+            .read()
+            .map(|current| current.clone())
+            .unwrap_or_else(|err| err.into_inner().clone())
+    }
+
+    pub fn replace(&self, configuration: Configuration) {
+        match self.current.write() {
+            Ok(mut current) => *current = Arc::new(configuration),
+            Err(err) => *err.into_inner() = Arc::new(configuration),
+        }
+    }
+}
+
+pub const PATH: &str = "takure.toml";
+
 impl Configuration {
     pub fn load() -> Result<Self> {
-        if !Path::new("takure.toml").exists() {
-            File::create("takure.toml")
+        let path = paths::resolve(PATH);
+        if !Path::new(&path).exists() {
+            File::create(&path)
                 .and_then(|mut file| file.write_all(include_bytes!("../takure.toml")))
                 .map_err(|err| anyhow::anyhow!("Could not create default config file: {}", err))?;
+        } else if let Err(err) = migration::migrate(&path, include_str!("../takure.toml")) {
+            // The file is still usable, only missing the new options
+            error!("{:#}", err);
         }
 
-        confy::load_path("takure.toml")
-            .map_err(|err| anyhow::anyhow!("Could not load config: {}", err))
+        let text = std::fs::read_to_string(&path)
+            .map_err(|err| anyhow::anyhow!("Could not read {}: {:#}", PATH, err))?;
+
+        // Every problem is reported at once, so that they can all be fixed in one go
+        let (configuration, diagnostics) = validation::parse(&text);
+        let mut errors = 0;
+        for diagnostic in &diagnostics {
+            if diagnostic.error {
+                errors += 1;
+                error!("{}", diagnostic);
+            } else {
+                warn!("{}", diagnostic);
+            }
+        }
+
+        match configuration {
+            Some(configuration) if errors == 0 => Ok(configuration),
+            _ => Err(anyhow::anyhow!("{} has {} error(s)", PATH, errors.max(1))),
+        }
     }
 }
 
@@ -30,6 +104,14 @@ pub struct GeneralConfiguration {
     pub enable: bool,
     #[serde(default = "default_timeout")]
     pub timeout: u64,
+    #[serde(default = "default_retry_interval")]
+    pub retry_interval: u64,
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    #[serde(default = "default_breaker_threshold")]
+    pub breaker_threshold: u32,
+    #[serde(default = "default_breaker_cooldown")]
+    pub breaker_cooldown: u64,
 }
 
 fn default_true() -> bool {
@@ -40,13 +122,271 @@ fn default_timeout() -> u64 {
     3000
 }
 
+fn default_retry_interval() -> u64 {
+    60
+}
+
+fn default_retries() -> u32 {
+    2
+}
+
+fn default_breaker_threshold() -> u32 {
+    3
+}
+
+fn default_breaker_cooldown() -> u64 {
+    120
+}
+
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct CardsConfiguration {
     pub whitelist: Vec<String>,
+    #[serde(default)]
+    pub blacklist: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryConfiguration {
+    #[serde(default = "default_true")]
+    pub enable: bool,
+}
+
+impl Default for HistoryConfiguration {
+    fn default() -> Self {
+        Self { enable: true }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OverlayConfiguration {
+    #[serde(default)]
+    pub enable: bool,
+    #[serde(default = "default_overlay_bind")]
+    pub bind: String,
+    #[serde(default = "default_overlay_port")]
+    pub port: u16,
+    /// Value of the Access-Control-Allow-Origin header, empty to not send it
+    #[serde(default)]
+    pub allowed_origin: String,
+}
+
+fn default_overlay_bind() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_overlay_port() -> u16 {
+    8484
+}
+
+impl Default for OverlayConfiguration {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            bind: default_overlay_bind(),
+            port: default_overlay_port(),
+            allowed_origin: String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NowPlayingConfiguration {
+    #[serde(default)]
+    pub enable: bool,
+    /// Empty to only write text files
+    #[serde(default = "default_now_playing_json")]
+    pub json: String,
+    #[serde(default)]
+    pub text: Vec<TextFileConfiguration>,
+}
+
+fn default_now_playing_json() -> String {
+    "now_playing.json".to_string()
+}
+
+impl Default for NowPlayingConfiguration {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            json: default_now_playing_json(),
+            text: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TextFileConfiguration {
+    pub path: String,
+    pub template: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScriptsConfiguration {
+    #[serde(default)]
+    pub enable: bool,
+    #[serde(default = "default_scripts_folder")]
+    pub folder: String,
+}
+
+fn default_scripts_folder() -> String {
+    "scripts".to_string()
+}
+
+impl Default for ScriptsConfiguration {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            folder: default_scripts_folder(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MqttConfiguration {
+    #[serde(default)]
+    pub enable: bool,
+    #[serde(default = "default_mqtt_host")]
+    pub host: String,
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+    #[serde(default = "default_mqtt_client_id")]
+    pub client_id: String,
+    /// Empty to connect without credentials
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+    #[serde(default = "default_mqtt_card_topic")]
+    pub card_topic: String,
+    #[serde(default = "default_mqtt_stage_topic")]
+    pub stage_topic: String,
+    /// Retained, empty to not publish it
+    #[serde(default = "default_mqtt_last_topic")]
+    pub last_topic: String,
+}
+
+fn default_mqtt_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_mqtt_client_id() -> String {
+    "takure".to_string()
+}
+
+fn default_mqtt_card_topic() -> String {
+    "takure/card".to_string()
+}
+
+fn default_mqtt_stage_topic() -> String {
+    "takure/stage".to_string()
+}
+
+fn default_mqtt_last_topic() -> String {
+    "takure/last".to_string()
+}
+
+impl Default for MqttConfiguration {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            host: default_mqtt_host(),
+            port: default_mqtt_port(),
+            client_id: default_mqtt_client_id(),
+            username: String::new(),
+            password: String::new(),
+            card_topic: default_mqtt_card_topic(),
+            stage_topic: default_mqtt_stage_topic(),
+            last_topic: default_mqtt_last_topic(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TachiConfiguration {
+    #[serde(default)]
+    pub name: Option<String>,
+    pub base_url: String,
+    pub api_key: String,
+    /// Other instances scores are also submitted to
+    #[serde(default)]
+    pub instances: Vec<TachiInstanceConfiguration>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TachiInstanceConfiguration {
+    #[serde(default)]
+    pub name: Option<String>,
     pub base_url: String,
     pub api_key: String,
 }
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RuleConfiguration {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub cards: Vec<String>,
+    #[serde(default)]
+    pub difficulties: Vec<Difficulty>,
+    #[serde(default)]
+    pub playtypes: Vec<Playtype>,
+    #[serde(default)]
+    pub lamps: Vec<TachiLamp>,
+    #[serde(default)]
+    pub mcodes: Vec<u32>,
+    #[serde(default)]
+    pub score_below: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SinkConfiguration {
+    #[serde(flatten)]
+    pub kind: SinkKind,
+    #[serde(default)]
+    pub on_failure: Option<FailurePolicy>,
+    #[serde(default)]
+    pub skip: Vec<RuleConfiguration>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SinkKind {
+    Tachi {
+        /// Name of the instance, every instance if not set
+        #[serde(default)]
+        instance: Option<String>,
+    },
+    Jsonl { path: String },
+    Database,
+    Webhook {
+        url: String,
+        /// JSON payload with `{{field}}` placeholders, the whole score event if not set
+        #[serde(default)]
+        template: Option<String>,
+    },
+    Command {
+        command: Vec<String>,
+        /// Time after which the command is killed, in seconds
+        #[serde(default = "default_command_timeout")]
+        timeout: u64,
+    },
+}
+
+fn default_command_timeout() -> u64 {
+    30
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailurePolicy {
+    /// Keep the score to submit it later
+    Queue,
+    /// Log the error and carry on with the next sinks
+    Ignore,
+    /// Log the error and skip the next sinks
+    Stop,
+}
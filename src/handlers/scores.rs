@@ -1,86 +1,65 @@
-use anyhow::{Ok, Result};
-use crate::{helpers, CONFIGURATION, TACHI_IMPORT_URL};
+use anyhow::Result;
+use crate::{cards, fingerprint, helpers, sinks, CONFIGURATION};
+use crate::cards::Access;
+use crate::sinks::ScoreEvent;
 use crate::types::game::{PlayerData2Data, PlayData3Data};
-use crate::types::tachi::{Difficulty, HitMeta, Import, ImportMeta, ImportScore, Judgements, Playtype, TachiLamp, Optional, Flare};
+use crate::types::tachi::Import;
 use log::{debug, info};
 use either::Either;
 
 pub fn process_scores(scores: Either<PlayerData2Data, PlayData3Data>) -> Result<()> {
 
-    let card = if let Some(card) = helpers::get_current_card_id() {
-        if !CONFIGURATION.cards.whitelist.is_empty()
-            && !CONFIGURATION.cards.whitelist.contains(&card)
-        {
-            info!(
-                "Card {} is not whitelisted, skipping score(s) submission",
-                card
-            );
-            return Ok(());
-        }
+    let ref_id = either::for_both!(&scores, scores => scores.ref_id.clone());
 
+    let card = if let Some(card) = helpers::get_current_card_id() {
         card
     } else {
         info!("Card ID is not set, skipping score(s) submission");
         return Ok(());
     };
 
+    if let Either::Left(scores) = &scores {
+        if scores.isgameover {
+            debug!("Aborting: isgameover is true");
+            sinks::card_out(&card);
+            return Ok(());
+        }
+    }
+
+    // Scores of ignored players are not even parsed, so that nothing about them is recorded
+    let configuration = CONFIGURATION.get();
+    match configuration.cards.access(&card, &ref_id) {
+        Access::Allowed => {}
+        Access::Blacklisted(entry) => {
+            info!(
+                "Card {} matches blacklist entry '{}', skipping score(s) submission",
+                cards::Card(&card),
+                entry
+            );
+            return Ok(());
+        }
+        Access::NotWhitelisted => {
+            info!("Card {} is not whitelisted, skipping score(s) submission", cards::Card(&card));
+            return Ok(());
+        }
+    }
+
     let time_achieved = std::time::UNIX_EPOCH
         .elapsed()
         .map(|duration| duration.as_millis())
         .map_err(|err| anyhow::anyhow!("Could not get time from System {:#}", err))?;
 
-    let (import_score, import_meta) = match &scores {
+    let (mut import_score, import_meta) = match &scores {
         Either::Left(scores) => {
-            if scores.isgameover {
-                debug!("Aborting: isgameover is true");
-                return Ok(());
-            }
-
             if scores.ref_id.starts_with("X000") {
                 info!("Guest play, skipping score(s) submission");
                 return Ok(());
             }
 
-            let highest_stage = scores.note.iter()
-                .filter(|n| n.stagenum != 0)
-                .max_by_key(|n| n.stagenum)
-                .cloned();
-
-            match highest_stage {
+            match scores.highest_stage() {
                 Some(highest_stage) => {
                     debug!("Selected note with highest stagenum != 0: {:#?}", highest_stage);
-
-                    let import_score = ImportScore {
-                        score: highest_stage.score,
-                        lamp: TachiLamp::from(highest_stage.clearkind),
-                        match_type: "inGameID".to_string(),
-                        identifier: highest_stage.mcode.to_string(),
-                        difficulty: Difficulty::from(highest_stage.notetype),
-                        time_achieved,
-                        judgements: Judgements {
-                            marvelous: highest_stage.judge_marvelous,
-                            perfect: highest_stage.judge_perfect,
-                            great: highest_stage.judge_great,
-                            good: highest_stage.judge_good,
-                            miss: highest_stage.judge_miss,
-                            ok: highest_stage.judge_ok,
-                        },
-                        hit_meta: HitMeta {
-                            fast: highest_stage.fastcount,
-                            slow: highest_stage.slowcount,
-                            max_combo: highest_stage.maxcombo,
-                            ex_score: highest_stage.ex_score,
-                        },
-                        optional: Optional::default(),
-                    };
-
-                    let import_meta = ImportMeta {
-                        game: "ddr".to_string(),
-                        play_type: Playtype::from(highest_stage.playstyle),
-                        service: "Takure".to_string(),
-                    };
-
-                    (import_score, import_meta)
+                    highest_stage.to_import(time_achieved)?
                 }
                 None => {
                     debug!("No valid note with stagenum != 0 found, skipping");
@@ -96,56 +75,22 @@ pub fn process_scores(scores: Either<PlayerData2Data, PlayData3Data>) -> Result<
 
             let result: crate::types::game::Result =
                 serde_json::from_value(scores.result.clone())?;
-
-            let import_score = ImportScore {
-                score: result.score,
-                lamp: TachiLamp::from(result.clearkind),
-                match_type: "inGameID".to_string(),
-                identifier: result.mcode.to_string(),
-                difficulty: Difficulty::from(result.difficulty),
-                time_achieved,
-                judgements: Judgements {
-                    marvelous: result.judge_marv,
-                    perfect: result.judge_perf,
-                    great: result.judge_great,
-                    good: result.judge_good,
-                    miss: result.judge_miss,
-                    ok: result.judge_ok,
-                },
-                hit_meta: HitMeta {
-                    fast: result.fastcount,
-                    slow: result.slowcount,
-                    max_combo: result.maxcombo,
-                    ex_score: result.ex_score,
-                },
-                optional: if result.flare_force == 0 {
-                    Optional::default()
-                } else {
-                    Optional { flare: Flare::from(result.flare_force) }
-                },
-            };
-
-            let import_meta = ImportMeta {
-                game: "ddr".to_string(),
-                play_type: Playtype::from(result.style),
-                service: "Takure".to_string(),
-            };
-
-            (import_score, import_meta)
+            result.to_import(time_achieved)?
         }
     };
 
-    let import = Import {
-        meta: import_meta,
-        scores: vec![import_score],
-    };
+    import_score.fingerprint = fingerprint::compute(&card, &import_score);
 
-    if cfg!(debug_assertions) {
-        debug!("Tachi API request data: {:#?}", import);
-    } else {
-        helpers::call_tachi("POST", TACHI_IMPORT_URL.as_str(), Some(import))?;
-    }
-    info!("Successfully imported score(s) for card {}", card);
+    let event = ScoreEvent {
+        card,
+        ref_id,
+        import: Import {
+            meta: import_meta,
+            scores: vec![import_score],
+        },
+        skip_reason: None,
+    };
+    sinks::dispatch(event);
 
     Ok(())
 }
@@ -1,13 +1,18 @@
 use anyhow::Result;
-use crate::CONFIGURATION;
+use crate::{circuit, CONFIGURATION};
+use crate::tachi::Instance;
+#[cfg(windows)]
 use crate::sys::{property_node_refer, NodeType};
 use crate::takure::CURRENT_CARD_ID;
-use log::{debug, error};
+use log::{debug, error, warn};
 use serde::{Deserialize, Serialize};
-use std::fmt::Debug;
+use std::collections::hash_map::RandomState;
+use std::fmt::{self, Debug};
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
 
 pub fn request_agent() -> ureq::Agent {
-    let timeout = CONFIGURATION.general.timeout;
+    let timeout = CONFIGURATION.get().general.timeout;
     let timeout = if timeout > 10000 { 10000 } else { timeout };
 
     ureq::builder()
@@ -15,10 +20,142 @@ pub fn request_agent() -> ureq::Agent {
         .build()
 }
 
+/// Error of a request to Tachi or to a webhook
+#[derive(Debug)]
+pub enum RequestError {
+    /// Network errors, rate limiting and server errors, which are worth retrying
+    Transient {
+        message: String,
+        retry_after: Option<Duration>,
+    },
+    /// The API key was refused, retrying will not help until the configuration is fixed
+    Unauthorized { message: String },
+    /// The request itself was refused, retrying it will never help
+    Rejected { message: String },
+}
+
+impl RequestError {
+    pub fn is_rejected(&self) -> bool {
+        matches!(self, RequestError::Rejected { .. })
+    }
+
+    fn retry_after(&self) -> Option<Option<Duration>> {
+        match self {
+            RequestError::Transient { retry_after, .. } => Some(*retry_after),
+            _ => None,
+        }
+    }
+
+    fn from_tachi(instance: &Instance, err: ureq::Error) -> Self {
+        match err {
+            ureq::Error::Status(code, response) => {
+                let retry_after = retry_after(&response);
+                let description = response
+                    .into_json::<serde_json::Value>()
+                    .ok()
+                    .and_then(|body| body["description"].as_str().map(str::to_string))
+                    .unwrap_or_else(|| "no description".to_string());
+
+                match code {
+                    401 => RequestError::Unauthorized {
+                        message: format!(
+                            "Tachi API key of {} was rejected (HTTP 401: {}), please check 'api_key' in takure.toml",
+                            instance, description
+                        ),
+                    },
+                    403 => RequestError::Unauthorized {
+                        message: format!(
+                            "Tachi API key of {} is not allowed to do this (HTTP 403: {}), please check its permissions",
+                            instance, description
+                        ),
+                    },
+                    408 | 429 | 500..=599 => RequestError::Transient {
+                        message: format!("Tachi API of {} is unavailable (HTTP {}: {})", instance, code, description),
+                        retry_after,
+                    },
+                    _ => RequestError::Rejected {
+                        message: format!("Tachi API of {} refused the request (HTTP {}: {})", instance, code, description),
+                    },
+                }
+            }
+            ureq::Error::Transport(transport) => RequestError::Transient {
+                message: format!("Could not reach Tachi API of {}: {:#}", instance, transport),
+                retry_after: None,
+            },
+        }
+    }
+
+    pub fn from_webhook(name: &str, err: ureq::Error) -> Self {
+        match err {
+            ureq::Error::Status(code @ (408 | 429 | 500..=599), response) => RequestError::Transient {
+                message: format!("Webhook {} is unavailable (HTTP {})", name, code),
+                retry_after: retry_after(&response),
+            },
+            ureq::Error::Status(code, _) => RequestError::Rejected {
+                message: format!("Webhook {} refused the request (HTTP {})", name, code),
+            },
+            ureq::Error::Transport(transport) => RequestError::Transient {
+                message: format!("Could not reach webhook {}: {:#}", name, transport),
+                retry_after: None,
+            },
+        }
+    }
+}
+
+fn retry_after(response: &ureq::Response) -> Option<Duration> {
+    response
+        .header("Retry-After")
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RequestError::Transient { message, .. }
+            | RequestError::Unauthorized { message }
+            | RequestError::Rejected { message } => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+/// Returns true if the API key was refused, which no retry will fix until the configuration changes
+pub fn is_unauthorized(err: &anyhow::Error) -> bool {
+    matches!(err.downcast_ref::<RequestError>(), Some(RequestError::Unauthorized { .. }))
+}
+
+/// Returns true if the error is the server refusing the request itself, which should not be retried
+pub fn is_rejected(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<RequestError>()
+        .map(RequestError::is_rejected)
+        .unwrap_or(false)
+}
+
+const BACKOFF_BASE: u64 = 500;
+const BACKOFF_MAX: u64 = 30000;
+
+/// Exponential backoff with equal jitter, waiting between half and all of the delay, unless the
+/// server told us how long to wait
+fn backoff(attempt: u32, retry_after: Option<Duration>) -> Duration {
+    if let Some(retry_after) = retry_after {
+        return retry_after.min(Duration::from_millis(BACKOFF_MAX));
+    }
+
+    let max = BACKOFF_BASE
+        .saturating_mul(1 << attempt.min(16))
+        .min(BACKOFF_MAX);
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_millis(max / 2 + random % (max / 2 + 1))
+}
+
 fn request<T>(
+    instance: &Instance,
     method: impl AsRef<str>,
     url: impl AsRef<str>,
     body: Option<T>,
+    retries: u32,
 ) -> Result<ureq::Response>
 where
     T: Serialize + Debug,
@@ -29,46 +166,104 @@ where
     let url = url.as_ref();
     debug!("{} request to {} with body: {:#?}", method, url, body);
 
-    let authorization = format!("Bearer {}", CONFIGURATION.tachi.api_key);
-    let request = agent
-        .request(method, url)
-        .set("Authorization", authorization.as_str());
-    let response = match body {
-        Some(body) => request.send_json(body),
-        None => request.call(),
-    }
-    .map_err(|err| anyhow::anyhow!("Could not reach Tachi API: {:#}", err))?;
+    let authorization = format!("Bearer {}", instance.api_key());
+    with_retries(
+        retries,
+        || {
+            let request = agent
+                .request(method, url)
+                .set("Authorization", authorization.as_str());
+            let result = match &body {
+                Some(body) => request.send_json(body),
+                None => request.call(),
+            };
 
-    Ok(response)
+            match result {
+                Ok(response) => {
+                    circuit::record_success(instance);
+                    Ok(response)
+                }
+                Err(err) => Err(RequestError::from_tachi(instance, err)),
+            }
+        },
+        || {
+            circuit::record_failure(instance);
+            !circuit::is_open(instance)
+        },
+    )
+}
+
+/// Sends a request until it succeeds, fails permanently or runs out of retries
+///
+/// `on_transient` is called after each transient failure, and returns false to stop retrying.
+/// Retries sleep between attempts, they must never happen on the game thread.
+pub fn with_retries<R>(
+    retries: u32,
+    mut send: impl FnMut() -> std::result::Result<R, RequestError>,
+    mut on_transient: impl FnMut() -> bool,
+) -> Result<R> {
+    let mut attempt = 0;
+    loop {
+        let err = match send() {
+            Ok(response) => return Ok(response),
+            Err(err) => err,
+        };
+
+        let retry_after = match err.retry_after() {
+            Some(retry_after) => retry_after,
+            None => return Err(err.into()),
+        };
+        if !on_transient() || attempt >= retries {
+            return Err(err.into());
+        }
+
+        let delay = backoff(attempt, retry_after);
+        warn!("{}, retrying in {}ms", err, delay.as_millis());
+        std::thread::sleep(delay);
+        attempt += 1;
+    }
 }
 
-pub fn call_tachi<T>(method: impl AsRef<str>, url: impl AsRef<str>, body: Option<T>) -> Result<()>
+/// Sends a single request to Tachi, failures being retried later by the caller
+pub fn request_tachi<T, R>(
+    instance: &Instance,
+    method: impl AsRef<str>,
+    url: impl AsRef<str>,
+    body: Option<T>,
+) -> Result<R>
 where
     T: Serialize + Debug,
+    R: for<'de> Deserialize<'de> + Debug,
 {
-    let response = request(method, url, body)?;
-    let response: serde_json::Value = response.into_json()?;
-    debug!("Tachi API response: {:#?}", response);
-
-    Ok(())
+    request_tachi_with_retries(instance, method, url, body, 0)
 }
 
-pub fn request_tachi<T, R>(
+/// Sends a request to Tachi, retrying transient failures with a backoff
+pub fn request_tachi_with_retries<T, R>(
+    instance: &Instance,
     method: impl AsRef<str>,
     url: impl AsRef<str>,
     body: Option<T>,
+    retries: u32,
 ) -> Result<R>
 where
     T: Serialize + Debug,
     R: for<'de> Deserialize<'de> + Debug,
 {
-    let response = request(method, url, body)?;
+    let response = request(instance, method, url, body, retries)?;
     let response = response.into_json()?;
     debug!("Tachi API response: {:#?}", response);
 
     Ok(response)
 }
 
+/// Turns a name into something usable in a file name
+pub fn file_slug(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c.to_ascii_lowercase() } else { '_' })
+        .collect()
+}
+
 pub fn get_current_card_id() -> Option<String> {
     let guard = CURRENT_CARD_ID.read().unwrap_or_else(|err| {
         error!("Current card ID RwLock is poisoned: {:#}", err);
@@ -78,6 +273,7 @@ pub fn get_current_card_id() -> Option<String> {
     guard.clone()
 }
 
+#[cfg(windows)]
 pub fn read_node_str(node: *const (), path: *const u8, length: usize) -> Option<String> {
     let mut buffer = [0u8; 32];
     let result = unsafe {
@@ -0,0 +1,248 @@
+use anyhow::Result;
+use crate::types::tachi::{Flare, Import, ImportScore};
+use crate::{music, paths, CONFIGURATION};
+use lazy_static::lazy_static;
+use log::{debug, error};
+use rusqlite::{params, Connection};
+use std::sync::{Mutex, MutexGuard};
+
+const DATABASE_PATH: &str = "takure.db";
+
+/// Schema migrations, applied in order and tracked with the `user_version` pragma
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE scores (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        fingerprint TEXT NOT NULL,
+        card TEXT NOT NULL,
+        ref_id TEXT NOT NULL,
+        playtype TEXT NOT NULL,
+        mcode TEXT NOT NULL,
+        difficulty TEXT NOT NULL,
+        score INTEGER NOT NULL,
+        ex_score INTEGER NOT NULL,
+        lamp TEXT NOT NULL,
+        flare INTEGER,
+        max_combo INTEGER NOT NULL,
+        fast INTEGER NOT NULL,
+        slow INTEGER NOT NULL,
+        marvelous INTEGER NOT NULL,
+        perfect INTEGER NOT NULL,
+        great INTEGER NOT NULL,
+        good INTEGER NOT NULL,
+        miss INTEGER NOT NULL,
+        ok INTEGER NOT NULL,
+        time_achieved INTEGER NOT NULL,
+        status TEXT NOT NULL,
+        reason TEXT,
+        response TEXT,
+        updated_at INTEGER NOT NULL
+    );
+    CREATE UNIQUE INDEX scores_fingerprint ON scores (fingerprint);
+    CREATE INDEX scores_card_time ON scores (card, time_achieved);",
+    "ALTER TABLE scores ADD COLUMN title TEXT;",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// Tachi confirmed receiving the score
+    Submitted,
+    /// Waiting in the local queue to be submitted
+    Queued,
+    /// Not submitted because of the card lists or a submission rule
+    Skipped,
+    /// Tachi refused the score
+    Rejected,
+    /// Not submitted because Takure was built in debug mode
+    Unsubmitted,
+    /// Could not be submitted, and was not queued
+    Failed,
+}
+
+impl Status {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Status::Submitted => "submitted",
+            Status::Queued => "queued",
+            Status::Skipped => "skipped",
+            Status::Rejected => "rejected",
+            Status::Unsubmitted => "unsubmitted",
+            Status::Failed => "failed",
+        }
+    }
+}
+
+lazy_static! {
+    static ref DATABASE: Mutex<Option<Connection>> = Mutex::new(None);
+}
+
+fn migrate(connection: &mut Connection) -> Result<()> {
+    let version: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(migration)?;
+        transaction.pragma_update(None, "user_version", index + 1)?;
+        transaction.commit()?;
+        debug!("Applied score history migration {}", index + 1);
+    }
+
+    Ok(())
+}
+
+fn open() -> Result<Connection> {
+    let mut connection = Connection::open(paths::resolve(DATABASE_PATH))
+        .map_err(|err| anyhow::anyhow!("Could not open score history: {:#}", err))?;
+    migrate(&mut connection)
+        .map_err(|err| anyhow::anyhow!("Could not migrate score history: {:#}", err))?;
+
+    Ok(connection)
+}
+
+fn lock() -> MutexGuard<'static, Option<Connection>> {
+    DATABASE.lock().unwrap_or_else(|err| {
+        error!("Score history Mutex is poisoned: {:#}", err);
+        err.into_inner()
+    })
+}
+
+fn with_database(f: impl FnOnce(&Connection) -> Result<()>) {
+    if !CONFIGURATION.get().history.enable {
+        return;
+    }
+
+    let mut guard = lock();
+    if guard.is_none() {
+        match open() {
+            Ok(connection) => *guard = Some(connection),
+            Err(err) => {
+                error!("{:#}", err);
+                return;
+            }
+        }
+    }
+
+    if let Some(connection) = guard.as_ref() {
+        if let Err(err) = f(connection) {
+            error!("Could not write score history: {:#}", err);
+        }
+    }
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+fn to_text<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|value| value.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// Records every score of an import, or updates their status if they were already recorded
+pub fn record(
+    card: &str,
+    ref_id: &str,
+    import: &Import,
+    status: Status,
+    reason: Option<&str>,
+    response: Option<&serde_json::Value>,
+) {
+    let response = response.map(|response| response.to_string());
+    with_database(|connection| {
+        for score in &import.scores {
+            let flare = match score.optional.flare {
+                Flare::None => None,
+                ref flare => Some(flare.clone() as u8),
+            };
+
+            connection.execute(
+                "INSERT INTO scores (
+                    fingerprint, card, ref_id, playtype, mcode, difficulty, score, ex_score, lamp,
+                    flare, max_combo, fast, slow, marvelous, perfect, great, good, miss, ok,
+                    time_achieved, status, reason, response, updated_at, title
+                ) VALUES (
+                    ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17,
+                    ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25
+                ) ON CONFLICT (fingerprint) DO UPDATE SET
+                    status = excluded.status,
+                    reason = excluded.reason,
+                    response = excluded.response,
+                    updated_at = excluded.updated_at",
+                params![
+                    score.fingerprint,
+                    card,
+                    ref_id,
+                    to_text(&import.meta.play_type),
+                    score.identifier,
+                    to_text(&score.difficulty),
+                    score.score,
+                    score.hit_meta.ex_score,
+                    to_text(&score.lamp),
+                    flare,
+                    score.hit_meta.max_combo,
+                    score.hit_meta.fast,
+                    score.hit_meta.slow,
+                    score.judgements.marvelous,
+                    score.judgements.perfect,
+                    score.judgements.great,
+                    score.judgements.good,
+                    score.judgements.miss,
+                    score.judgements.ok,
+                    score.time_achieved as i64,
+                    status.as_str(),
+                    reason,
+                    response,
+                    now(),
+                    music::title(&score.identifier),
+                ],
+            )?;
+        }
+
+        Ok(())
+    });
+}
+
+/// Updates the status of already recorded scores, when they are submitted from the queue
+pub fn update_status(
+    import: &Import,
+    status: Status,
+    reason: Option<&str>,
+    response: Option<&serde_json::Value>,
+) {
+    let response = response.map(|response| response.to_string());
+    with_database(|connection| {
+        for score in &import.scores {
+            connection.execute(
+                "UPDATE scores SET status = ?1, reason = ?2, response = ?3, updated_at = ?4
+                WHERE fingerprint = ?5",
+                params![status.as_str(), reason, response, now(), score.fingerprint],
+            )?;
+        }
+
+        Ok(())
+    });
+}
+
+/// Returns the best score previously recorded on a chart, not counting the given play
+pub fn personal_best(card: &str, import: &Import, score: &ImportScore) -> Option<u32> {
+    let mut best = None;
+    with_database(|connection| {
+        best = connection.query_row(
+            "SELECT MAX(score) FROM scores
+            WHERE card = ?1 AND playtype = ?2 AND mcode = ?3 AND difficulty = ?4
+            AND fingerprint != ?5 AND status != 'skipped'",
+            params![
+                card,
+                to_text(&import.meta.play_type),
+                score.identifier,
+                to_text(&score.difficulty),
+                score.fingerprint,
+            ],
+            |row| row.get(0),
+        )?;
+
+        Ok(())
+    });
+
+    best
+}
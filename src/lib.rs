@@ -1,55 +1,55 @@
+// Without the hook, most of the crate is only reached by tests on other platforms
+#![cfg_attr(not(windows), allow(dead_code))]
+
+mod cards;
+mod circuit;
 mod configuration;
 mod handlers;
 mod helpers;
+mod history;
 mod log;
+mod migration;
+mod music;
+mod paths;
+mod plugin;
+mod queue;
+mod rules;
+mod scripts;
+mod sinks;
+#[cfg(windows)]
 mod sys;
+mod tachi;
 mod takure;
-mod types;
+mod validation;
 
 use crate::log::Logger;
+#[cfg(windows)]
 use crate::takure::{hook_init, hook_release};
-use ::log::{error, info};
+use ::log::{error, info, warn};
 use configuration::Configuration;
 use lazy_static::lazy_static;
-use url::Url;
+use takure_common::{fingerprint, types};
+#[cfg(windows)]
 use winapi::shared::minwindef::{BOOL, DWORD, HINSTANCE, LPVOID, TRUE};
+#[cfg(windows)]
 use winapi::um::consoleapi::AllocConsole;
+#[cfg(windows)]
 use winapi::um::winnt::{DLL_PROCESS_ATTACH, DLL_PROCESS_DETACH};
 
 lazy_static! {
-    pub static ref CONFIGURATION: Configuration = {
-        let result = Configuration::load();
-        if let Err(err) = result {
-            error!("{:#}", err);
-            std::process::exit(1);
-        }
-
-        result.unwrap()
-    };
-
-    pub static ref TACHI_STATUS_URL: String = {
-        let result = Url::parse(&CONFIGURATION.tachi.base_url)
-            .and_then(|url| url.join("/api/v1/status"));
-        if let Err(err) = result {
-            error!("Could not parse Tachi status URL: {:#}", err);
-            std::process::exit(1);
-        }
-
-        result.unwrap().to_string()
-    };
-    pub static ref TACHI_IMPORT_URL: String = {
-        let result = Url::parse(&CONFIGURATION.tachi.base_url)
-            .and_then(|url| url.join("/ir/direct-manual/import"));
-        if let Err(err) = result {
-            error!("Could not parse Tachi import URL: {:#}", err);
-            std::process::exit(1);
-        }
-
-        result.unwrap().to_string()
+    pub static ref CONFIGURATION: configuration::Shared = {
+        let configuration = Configuration::load().unwrap_or_else(|err| {
+            // Exiting would close the game, disabling the hook lets the player keep playing
+            error!("{:#}, Takure is disabled until it is fixed and the game is restarted", err);
+            Configuration::default()
+        });
+
+        configuration::Shared::new(configuration)
     };
 }
 
 fn init_logger() {
+    let (logger, problem) = Logger::new();
     env_logger::builder()
         .filter_level(::log::LevelFilter::Error)
         .filter_module(
@@ -61,7 +61,7 @@ fn init_logger() {
             },
         )
         .parse_default_env()
-        .target(env_logger::Target::Pipe(Box::new(Logger::new())))
+        .target(env_logger::Target::Pipe(Box::new(logger)))
         .format(|f, record| {
             use crate::log::{colored_level, max_target_width, Padded};
             use std::io::Write;
@@ -83,6 +83,10 @@ fn init_logger() {
             writeln!(f, "[{}] {} {} -> {}", time, level, target, record.args())
         })
         .init();
+
+    if let Some(problem) = problem {
+        warn!("{}", problem);
+    }
 }
 
 fn print_infos() {
@@ -95,8 +99,11 @@ fn print_infos() {
     if let Some(build_date) = option_env!("VERGEN_BUILD_DATE") {
         info!("Build date: {}", build_date);
     }
+
+    paths::log_directory();
 }
 
+#[cfg(windows)]
 #[cfg_attr(target_arch = "x86", crochet::hook("libavs-win32-ea3.dll", "XE592acd00008c"))]
 #[cfg_attr(target_arch = "x86_64", crochet::hook("libavs-win64-ea3.dll", "XEyy2igh000007"))]
 unsafe extern "C" fn avs_ea3_boot_startup_hook(node: *const ()) -> i32 {
@@ -107,6 +114,7 @@ unsafe extern "C" fn avs_ea3_boot_startup_hook(node: *const ()) -> i32 {
     call_original!(node)
 }
 
+#[cfg(windows)]
 #[no_mangle]
 #[allow(non_snake_case, unused_variables)]
 extern "system" fn DllMain(dll_module: HINSTANCE, call_reason: DWORD, reserved: LPVOID) -> BOOL {
@@ -1,17 +1,39 @@
+use crate::paths;
 use std::fmt;
 use std::fs::File;
 use std::io::Write;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
+pub const PATH: &str = "takure.log";
+
 #[derive(Debug)]
 pub struct Logger {
-    file: File,
+    /// Missing when the log file could not be created, logs are then only written to the console
+    file: Option<File>,
 }
 
 impl Logger {
-    pub fn new() -> Self {
-        Self {
-            file: File::create("takure.log").unwrap(),
+    /// Creates the log file in the Takure folder, or in the working directory if that folder is
+    /// missing or read-only, also returning the problem to log once the logger is initialized
+    pub fn new() -> (Self, Option<String>) {
+        let path = paths::resolve(PATH);
+        let err = match File::create(&path) {
+            Ok(file) => return (Self { file: Some(file) }, None),
+            Err(err) => err,
+        };
+
+        match File::create(PATH) {
+            Ok(file) => (
+                Self { file: Some(file) },
+                Some(format!(
+                    "Could not create '{}': {}, logging to '{}' in the working directory instead",
+                    path, err, PATH
+                )),
+            ),
+            Err(_) => (
+                Self { file: None },
+                Some(format!("Could not create '{}': {}, logging to the console only", path, err)),
+            ),
         }
     }
 }
@@ -20,13 +42,19 @@ impl Write for Logger {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         // Ignore the result of the write to stdout, since it's not really important
         let _ = std::io::stdout().write(buf);
-        self.file.write(buf)
+        match &mut self.file {
+            Some(file) => file.write(buf),
+            None => Ok(buf.len()),
+        }
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
         // Ignore the result of the write to stdout, since it's not really important
         let _ = std::io::stdout().flush();
-        self.file.flush()
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
     }
 }
 
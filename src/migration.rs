@@ -0,0 +1,185 @@
+use anyhow::Result;
+use log::{debug, info};
+use toml_edit::{DocumentMut, Item, Table};
+
+/// Version of the bundled takure.toml, to increment when options are added to it
+pub const VERSION: i64 = 2;
+
+/// Adds the options of the bundled takure.toml missing from a file written by an older version,
+/// along with their comments, keeping the values and comments of the user
+///
+/// The previous file is kept next to it. Files which cannot be parsed are left as they are, for
+/// the validation to report why.
+pub fn migrate(path: &str, bundled: &str) -> Result<()> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|err| anyhow::anyhow!("Could not read {}: {:#}", path, err))?;
+    let mut document = match text.parse::<DocumentMut>() {
+        Ok(document) => document,
+        Err(_) => return Ok(()),
+    };
+
+    let version = document
+        .get("version")
+        .and_then(|version| version.as_integer())
+        .unwrap_or(0);
+    if version >= VERSION {
+        return Ok(());
+    }
+
+    let defaults = bundled
+        .parse::<DocumentMut>()
+        .map_err(|err| anyhow::anyhow!("Could not parse bundled {}: {:#}", path, err))?;
+
+    let mut added = Vec::new();
+    let mut position = document
+        .iter()
+        .filter_map(|(_, item)| item.as_table().and_then(Table::position))
+        .max()
+        .unwrap_or(0);
+    merge(document.as_table_mut(), defaults.as_table(), "", &mut added, &mut position);
+
+    // Keeps the comment written next to the version
+    if let Some(value) = document.get_mut("version").and_then(Item::as_value_mut) {
+        let decor = value.decor().clone();
+        *value = VERSION.into();
+        *value.decor_mut() = decor;
+    }
+    // A version added from the bundled file comes with its header, which the user file already has
+    if let Some(mut key) = document.key_mut("version").filter(|_| version == 0) {
+        let prefix = key.leaf_decor().prefix().and_then(|prefix| prefix.as_str());
+        if let Some(comment) = prefix.and_then(|prefix| prefix.rsplit("\n\n").next()) {
+            let comment = comment.to_string();
+            key.leaf_decor_mut().set_prefix(comment);
+        }
+    }
+
+    let backup = format!("{}.v{}.bak", path, version);
+    std::fs::copy(path, &backup)
+        .map_err(|err| anyhow::anyhow!("Could not back up {} to '{}': {:#}", path, backup, err))?;
+    std::fs::write(path, document.to_string())
+        .map_err(|err| anyhow::anyhow!("Could not write {}: {:#}", path, err))?;
+
+    info!(
+        "Updated {} from version {} to {}, the previous file was saved as '{}'",
+        path, version, VERSION, backup
+    );
+    if !added.is_empty() {
+        info!("New options: {}", added.join(", "));
+    }
+
+    Ok(())
+}
+
+/// Inserts the keys and tables of `defaults` missing from `table`, recursing into tables both have
+fn merge(table: &mut Table, defaults: &Table, prefix: &str, added: &mut Vec<String>, position: &mut usize) {
+    for (key, default) in defaults.iter() {
+        let path = if prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+
+        match (table.get_mut(key), default) {
+            (Some(Item::Table(existing)), Item::Table(default)) => {
+                merge(existing, default, &path, added, position);
+            }
+            (Some(_), _) => {}
+            (None, default) => {
+                let mut item = default.clone();
+                // New tables go after the tables of the user, in the order of the bundled file
+                if let Some(table) = item.as_table_mut() {
+                    *position += 1;
+                    table.set_position(*position);
+                }
+                match defaults.key(key) {
+                    Some(formatted) => table.insert_formatted(formatted, item),
+                    None => table.insert(key, item),
+                };
+                debug!("Added '{}' to the configuration", path);
+                added.push(path);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const BUNDLED: &str = include_str!("../takure.toml");
+
+    fn write(name: &str, content: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("takure-migration-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("takure.toml");
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn adds_new_options_and_keeps_the_user_ones() {
+        let original = "\
+# Cabinet at home
+
+[general]
+# Slow connection
+timeout = 10000
+retries = 5
+
+[cards]
+whitelist = ['E004010027A5FC68'] # Mine
+
+[tachi]
+base_url = 'https://kamai.tachi.ac/'
+api_key = 'secret'
+";
+        let path = write("v0", original);
+        migrate(path.to_str().unwrap(), BUNDLED).unwrap();
+
+        let migrated = std::fs::read_to_string(&path).unwrap();
+        for kept in [
+            "# Cabinet at home",
+            "# Slow connection\ntimeout = 10000",
+            "retries = 5",
+            "whitelist = ['E004010027A5FC68'] # Mine",
+            "api_key = 'secret'",
+        ] {
+            assert!(migrated.contains(kept), "'{}' is missing from:\n{}", kept, migrated);
+        }
+        for added in ["breaker_threshold = 3", "[overlay]", "allowed_origin = ''", "[scripts]"] {
+            assert!(migrated.contains(added), "'{}' was not added to:\n{}", added, migrated);
+        }
+        assert!(migrated.contains(&format!("version = {}", VERSION)));
+        // The header of the bundled file is not added again
+        assert!(!migrated.contains("Changes to this file are applied"));
+
+        let (configuration, diagnostics) = crate::validation::parse(&migrated);
+        let configuration = configuration.unwrap();
+        assert!(diagnostics.iter().all(|diagnostic| !diagnostic.error));
+        assert_eq!(configuration.general.timeout, 10000);
+        assert_eq!(configuration.general.retries, 5);
+        assert_eq!(configuration.general.breaker_threshold, 3);
+        assert_eq!(configuration.tachi.api_key, "secret");
+        assert!(!configuration.overlay.enable);
+
+        let backup = std::fs::read_to_string(format!("{}.v0.bak", path.display())).unwrap();
+        assert_eq!(backup, original);
+
+        // Migrating again changes nothing
+        migrate(path.to_str().unwrap(), BUNDLED).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), migrated);
+    }
+
+    #[test]
+    fn leaves_current_files_unchanged() {
+        let current = BUNDLED.replace("[general]\n", "[general] # Edited\n");
+        let path = write("current", &current);
+        migrate(path.to_str().unwrap(), BUNDLED).unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), current.as_bytes());
+        // Without a backup
+        assert_eq!(std::fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,31 @@
+use lazy_static::lazy_static;
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::path::Path;
+use takure_common::music::parse;
+
+const MUSICDB_PATH: &str = "data/gamedata/musicdb.xml";
+
+lazy_static! {
+    static ref TITLES: HashMap<String, String> = load();
+}
+
+fn load() -> HashMap<String, String> {
+    if !Path::new(MUSICDB_PATH).exists() {
+        debug!("Could not find '{}', song titles will not be available", MUSICDB_PATH);
+        return HashMap::new();
+    }
+
+    match std::fs::read(MUSICDB_PATH) {
+        Ok(content) => parse(&String::from_utf8_lossy(&content)),
+        Err(err) => {
+            warn!("Could not read '{}': {:#}", MUSICDB_PATH, err);
+            HashMap::new()
+        }
+    }
+}
+
+/// Returns the title of a song from its code, if the game music database could be read
+pub fn title(mcode: &str) -> Option<&'static str> {
+    TITLES.get(mcode).map(String::as_str)
+}
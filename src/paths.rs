@@ -0,0 +1,98 @@
+use lazy_static::lazy_static;
+use log::info;
+use std::path::{Path, PathBuf};
+#[cfg(windows)]
+use winapi::shared::minwindef::{HMODULE, MAX_PATH};
+#[cfg(windows)]
+use winapi::um::libloaderapi::{
+    GetModuleFileNameW, GetModuleHandleExW, GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS,
+    GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+};
+
+/// Environment variable overriding the folder Takure reads and writes its files in
+pub const DIRECTORY_VARIABLE: &str = "TAKURE_DIR";
+
+/// Where the folder of Takure files comes from
+#[derive(Debug, Clone, Copy)]
+enum Source {
+    Variable,
+    Module,
+    WorkingDirectory,
+}
+
+lazy_static! {
+    static ref DIRECTORY: (PathBuf, Source) = find_directory();
+}
+
+/// Returns the folder containing the Takure DLL, which the game does not always run from
+#[cfg(windows)]
+fn module_directory() -> Option<PathBuf> {
+    let mut module: HMODULE = std::ptr::null_mut();
+    let found = unsafe {
+        GetModuleHandleExW(
+            GET_MODULE_HANDLE_EX_FLAG_FROM_ADDRESS | GET_MODULE_HANDLE_EX_FLAG_UNCHANGED_REFCOUNT,
+            module_directory as *const u16,
+            &mut module,
+        )
+    };
+    if found == 0 {
+        return None;
+    }
+
+    let mut buffer = vec![0u16; MAX_PATH];
+    loop {
+        let length =
+            unsafe { GetModuleFileNameW(module, buffer.as_mut_ptr(), buffer.len() as u32) } as usize;
+        if length == 0 {
+            return None;
+        }
+        // The name was truncated, retried with a larger buffer for long paths
+        if length == buffer.len() {
+            buffer.resize(buffer.len() * 2, 0);
+            continue;
+        }
+
+        let path = PathBuf::from(String::from_utf16_lossy(&buffer[..length]));
+        return path.parent().map(Path::to_path_buf);
+    }
+}
+
+/// Takure is only loaded by the game on Windows
+#[cfg(not(windows))]
+fn module_directory() -> Option<PathBuf> {
+    None
+}
+
+fn find_directory() -> (PathBuf, Source) {
+    if let Some(directory) = std::env::var_os(DIRECTORY_VARIABLE).filter(|value| !value.is_empty()) {
+        return (PathBuf::from(directory), Source::Variable);
+    }
+
+    if let Some(directory) = module_directory() {
+        return (directory, Source::Module);
+    }
+
+    let directory = std::env::current_dir().unwrap_or_default();
+    (directory, Source::WorkingDirectory)
+}
+
+/// Returns the folder Takure files are read from and written to
+pub fn directory() -> &'static Path {
+    &DIRECTORY.0
+}
+
+/// Resolves a path of Takure files, relative paths being relative to the Takure folder rather than
+/// to the folder the game runs from
+pub fn resolve(path: &str) -> String {
+    directory().join(path).to_string_lossy().into_owned()
+}
+
+/// Logs where Takure files are, to find them when the game is started by a launcher
+pub fn log_directory() {
+    let source = match DIRECTORY.1 {
+        Source::Variable => format!("set by {}", DIRECTORY_VARIABLE),
+        Source::Module => "folder of the DLL".to_string(),
+        Source::WorkingDirectory => "working directory, the DLL folder could not be found".to_string(),
+    };
+    info!("Takure files are in '{}' ({})", directory().display(), source);
+}
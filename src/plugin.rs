@@ -0,0 +1,129 @@
+use lazy_static::lazy_static;
+use log::{error, info};
+use std::ffi::{c_char, c_void};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+/// Version of the ABI, incremented when fields are appended to [`TakureEvent`]
+pub const ABI_VERSION: u32 = 1;
+
+pub const EVENT_CARD_IN: u32 = 1;
+pub const EVENT_CARD_OUT: u32 = 2;
+pub const EVENT_SCORE: u32 = 3;
+
+/// An event given to callbacks of other modules injected in the game, strings are UTF-8 and
+/// nul-terminated, and only valid during the call
+///
+/// Fields are only ever appended, modules should check `version` or `size` before reading fields
+/// newer than the version they were built against. Score fields are zero or null for card events,
+/// `flare` and `skip_reason` are also null when not set.
+#[repr(C)]
+#[derive(Debug)]
+pub struct TakureEvent {
+    pub version: u32,
+    pub size: u32,
+    pub kind: u32,
+    pub card: *const c_char,
+    pub konami_id: *const c_char,
+    pub title: *const c_char,
+    pub mcode: *const c_char,
+    pub playtype: *const c_char,
+    pub difficulty: *const c_char,
+    pub lamp: *const c_char,
+    pub flare: *const c_char,
+    pub score: u32,
+    pub ex_score: u32,
+    pub max_combo: u32,
+    pub marvelous: u32,
+    pub perfect: u32,
+    pub great: u32,
+    pub good: u32,
+    pub ok: u32,
+    pub miss: u32,
+    pub fast: u32,
+    pub slow: u32,
+    pub skip_reason: *const c_char,
+}
+
+pub type TakureCallback = extern "C" fn(event: *const TakureEvent, user_data: *mut c_void);
+
+struct Registration {
+    handle: i32,
+    callback: TakureCallback,
+    /// Only ever given back to the module, stored as an address so that it can be shared
+    user_data: usize,
+}
+
+lazy_static! {
+    static ref CALLBACKS: Mutex<Vec<Registration>> = Mutex::new(Vec::new());
+}
+
+static NEXT_HANDLE: AtomicI32 = AtomicI32::new(1);
+
+fn lock() -> MutexGuard<'static, Vec<Registration>> {
+    CALLBACKS.lock().unwrap_or_else(|err| {
+        error!("Plugin Mutex is poisoned: {:#}", err);
+        err.into_inner()
+    })
+}
+
+pub fn has_callbacks() -> bool {
+    !lock().is_empty()
+}
+
+/// Gives an event to every registered callback
+pub fn emit(event: &TakureEvent) {
+    // Callbacks are copied so that they can unregister themselves
+    let callbacks = lock()
+        .iter()
+        .map(|registration| (registration.callback, registration.user_data))
+        .collect::<Vec<_>>();
+    for (callback, user_data) in callbacks {
+        callback(event, user_data as *mut c_void);
+    }
+}
+
+/// Returns the version of the ABI implemented by this Takure build
+#[no_mangle]
+pub extern "C" fn takure_abi_version() -> u32 {
+    ABI_VERSION
+}
+
+/// Registers a callback receiving card scans, game ends and stage results, it runs on a Takure
+/// thread, one event at a time, and should return quickly, `user_data` is given back to it as is
+///
+/// Returns a handle to unregister it, or -1 if the callback is null.
+#[no_mangle]
+pub extern "C" fn takure_register_callback(
+    callback: Option<TakureCallback>,
+    user_data: *mut c_void,
+) -> i32 {
+    let callback = match callback {
+        Some(callback) => callback,
+        None => return -1,
+    };
+
+    let handle = NEXT_HANDLE.fetch_add(1, Ordering::Relaxed);
+    lock().push(Registration {
+        handle,
+        callback,
+        user_data: user_data as usize,
+    });
+    info!("Plugin callback {} registered", handle);
+
+    handle
+}
+
+/// Unregisters a callback, returns 0 on success or -1 if the handle is unknown
+#[no_mangle]
+pub extern "C" fn takure_unregister_callback(handle: i32) -> i32 {
+    let mut callbacks = lock();
+    match callbacks.iter().position(|registration| registration.handle == handle) {
+        Some(index) => {
+            callbacks.remove(index);
+            info!("Plugin callback {} unregistered", handle);
+            0
+        }
+        None => -1,
+    }
+}
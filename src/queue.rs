@@ -0,0 +1,340 @@
+use anyhow::Result;
+use crate::fingerprint::State;
+use crate::types::tachi::Import;
+use crate::history::{self, Status};
+use crate::tachi::Instance;
+use crate::{circuit, fingerprint, helpers, paths, CONFIGURATION};
+use lazy_static::lazy_static;
+use log::{debug, error, info, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+
+/// Queue of the main Tachi instance
+pub const QUEUE_PATH: &str = "takure.queue.jsonl";
+
+/// Entries waiting to be submitted, stored one JSON object per line
+pub struct Queue {
+    path: String,
+    locks: Arc<Locks>,
+}
+
+#[derive(Default)]
+struct Locks {
+    /// Only guards file accesses, never held during requests
+    file: Mutex<()>,
+    /// Held while the queue is flushed, so that entries are not submitted twice at once
+    flush: Mutex<()>,
+}
+
+/// An entry read from a queue, which stays in the queue until it is removed
+pub struct Entry<T> {
+    line: String,
+    pub value: T,
+}
+
+lazy_static! {
+    /// Locks of every queue file, shared by queues of the same file after a configuration reload
+    static ref LOCKS: Mutex<HashMap<String, Arc<Locks>>> = Mutex::new(HashMap::new());
+}
+
+impl Queue {
+    pub fn new(path: &str) -> Self {
+        let path = paths::resolve(path);
+        let locks = LOCKS
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .entry(path.clone())
+            .or_default()
+            .clone();
+
+        Queue { path, locks }
+    }
+
+    fn read_lines(&self) -> Result<Vec<String>> {
+        if !Path::new(&self.path).exists() {
+            return Ok(Vec::new());
+        }
+
+        let file = File::open(&self.path)
+            .map_err(|err| anyhow::anyhow!("Could not open queue '{}': {:#}", self.path, err))?;
+
+        let mut lines = Vec::new();
+        for line in BufReader::new(file).lines() {
+            let line = line
+                .map_err(|err| anyhow::anyhow!("Could not read queue '{}': {:#}", self.path, err))?;
+            if !line.trim().is_empty() {
+                lines.push(line);
+            }
+        }
+
+        Ok(lines)
+    }
+
+    /// Replaces the content of the queue, writing a temporary file then renaming it so that the
+    /// queue is never left half written
+    fn write_lines(&self, lines: &[String]) -> Result<()> {
+        let temporary = format!("{}.tmp", self.path);
+        let mut file = File::create(&temporary)
+            .map_err(|err| anyhow::anyhow!("Could not write queue '{}': {:#}", temporary, err))?;
+        for line in lines {
+            writeln!(file, "{}", line)
+                .map_err(|err| anyhow::anyhow!("Could not write queue '{}': {:#}", temporary, err))?;
+        }
+        file.sync_all()
+            .map_err(|err| anyhow::anyhow!("Could not write queue '{}': {:#}", temporary, err))?;
+        drop(file);
+
+        std::fs::rename(&temporary, &self.path)
+            .map_err(|err| anyhow::anyhow!("Could not replace queue '{}': {:#}", self.path, err))
+    }
+
+    fn append<T: Serialize>(&self, queued: &[T]) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|err| anyhow::anyhow!("Could not open queue '{}': {:#}", self.path, err))?;
+
+        for entry in queued {
+            let line = serde_json::to_string(entry)?;
+            writeln!(file, "{}", line)
+                .map_err(|err| anyhow::anyhow!("Could not write queue '{}': {:#}", self.path, err))?;
+        }
+
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ()> {
+        self.locks.file.lock().unwrap_or_else(|err| {
+            error!("Queue Mutex is poisoned: {:#}", err);
+            err.into_inner()
+        })
+    }
+
+    /// Returns a guard to hold while flushing the queue, or `None` if it is already being flushed
+    pub fn try_lock_flush(&self) -> Option<MutexGuard<'_, ()>> {
+        match self.locks.flush.try_lock() {
+            Ok(guard) => Some(guard),
+            Err(TryLockError::Poisoned(err)) => Some(err.into_inner()),
+            Err(TryLockError::WouldBlock) => None,
+        }
+    }
+
+    pub fn push<T: Serialize>(&self, entry: T) -> Result<()> {
+        let _guard = self.lock();
+        self.append(&[entry])
+    }
+
+    /// Returns every entry of the queue without removing them, dropping malformed entries
+    pub fn peek<T: DeserializeOwned>(&self) -> Result<Vec<Entry<T>>> {
+        let _guard = self.lock();
+        let lines = self.read_lines()?;
+
+        let mut entries = Vec::new();
+        let mut malformed = false;
+        for line in &lines {
+            match serde_json::from_str::<T>(line) {
+                Ok(value) => entries.push(Entry {
+                    line: line.clone(),
+                    value,
+                }),
+                Err(err) => {
+                    warn!("Dropping malformed entry of queue '{}': {:#}", self.path, err);
+                    malformed = true;
+                }
+            }
+        }
+
+        if malformed {
+            let valid = entries.iter().map(|entry| entry.line.clone()).collect::<Vec<_>>();
+            self.write_lines(&valid)?;
+        }
+
+        Ok(entries)
+    }
+
+    /// Removes an entry once it was submitted or dropped, keeping entries added in the meantime
+    pub fn remove<T>(&self, entry: &Entry<T>) -> Result<()> {
+        let _guard = self.lock();
+        let mut lines = self.read_lines()?;
+        if let Some(index) = lines.iter().position(|line| *line == entry.line) {
+            lines.remove(index);
+            self.write_lines(&lines)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let _guard = self.lock();
+        self.read_lines()
+            .map(|lines| lines.is_empty())
+            .unwrap_or(true)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueuedImport {
+    pub card: String,
+    pub import: Import,
+}
+
+/// Stores an import locally so that it can be submitted later
+pub fn push(instance: &Instance, card: &str, import: &Import) -> Result<()> {
+    instance.queue.push(QueuedImport {
+        card: card.to_string(),
+        import: import.clone(),
+    })
+}
+
+pub fn is_empty(instance: &Instance) -> bool {
+    instance.queue.is_empty()
+}
+
+/// Gets the last scores Tachi received from the user, to find out whether a previous request
+/// whose outcome is unknown was received
+fn recent_scores(instance: &Instance, import: &Import) -> Result<Vec<serde_json::Value>> {
+    let user = match instance.user.load(Ordering::Relaxed) {
+        0 => "me".to_string(),
+        user => user.to_string(),
+    };
+    let url = instance.url(&format!(
+        "/api/v1/users/{}/games/{}/{:?}/scores/recent",
+        user, import.meta.game, import.meta.play_type
+    ))?;
+    let response: serde_json::Value = helpers::request_tachi(instance, "GET", url, None::<()>)?;
+
+    Ok(response["body"]["scores"].as_array().cloned().unwrap_or_default())
+}
+
+/// Submits an import to Tachi, skipping the scores Tachi already confirmed receiving
+///
+/// Scores are recorded as pending before the request. Pending scores of a previous request, which
+/// might have reached Tachi even though it failed, are looked up among the recent scores of the
+/// user rather than sent again.
+///
+/// Returns the Tachi API response, or `None` if every score was already submitted. Transient
+/// failures are retried `retries` times, which is only done for queued scores.
+pub fn submit(instance: &Instance, import: &Import, retries: u32) -> Result<Option<serde_json::Value>> {
+    let mut recent = None;
+    let mut scores = Vec::new();
+    for score in &import.scores {
+        match instance.confirmed.state(&score.fingerprint) {
+            Some(State::Confirmed) => continue,
+            Some(State::Pending) => {
+                if recent.is_none() {
+                    recent = Some(recent_scores(instance, import)?);
+                }
+                if recent.as_deref().map(|recent| fingerprint::is_received(recent, score)) == Some(true) {
+                    info!("{} already received a score sent before, not sending it again", instance);
+                    instance.confirmed.confirm([score.fingerprint.as_str()])?;
+                    continue;
+                }
+            }
+            None => {}
+        }
+        scores.push(score.clone());
+    }
+    if scores.is_empty() {
+        info!("Score(s) were already submitted, skipping");
+        return Ok(None);
+    }
+
+    let import = Import {
+        meta: import.meta.clone(),
+        scores,
+    };
+    instance
+        .confirmed
+        .mark_pending(import.scores.iter().map(|score| score.fingerprint.as_str()))?;
+    let response: serde_json::Value =
+        helpers::request_tachi_with_retries(instance, "POST", &instance.import_url, Some(&import), retries)?;
+    instance.confirmed.confirm(import.scores.iter().map(|score| score.fingerprint.as_str()))?;
+
+    Ok(Some(response))
+}
+
+/// Submits every queued import to Tachi, removing each of them from the queue once it was
+/// submitted or rejected
+pub fn flush(instance: &'static Instance) -> Result<()> {
+    if !circuit::allow_request(instance) {
+        debug!("Circuit of {} is open, not submitting queued scores", instance);
+        return Ok(());
+    }
+
+    let _flushing = match instance.queue.try_lock_flush() {
+        Some(guard) => guard,
+        None => {
+            debug!("Queued scores of {} are already being submitted", instance);
+            return Ok(());
+        }
+    };
+
+    let mut queued = instance.queue.peek::<QueuedImport>()?;
+    if queued.is_empty() {
+        return Ok(());
+    }
+
+    // Fingerprints are not serialized, they are recomputed instead
+    for entry in queued.iter_mut() {
+        let QueuedImport { card, import } = &mut entry.value;
+        for score in import.scores.iter_mut() {
+            score.fingerprint = fingerprint::compute(card, score);
+        }
+    }
+
+    info!("Submitting {} queued score(s) to {}", queued.len(), instance);
+
+    let retries = CONFIGURATION.get().general.retries;
+    let total = queued.len();
+    for (index, entry) in queued.iter().enumerate() {
+        let import = &entry.value.import;
+        match submit(instance, import, retries) {
+            Ok(response) => {
+                if instance.main {
+                    history::update_status(import, Status::Submitted, None, response.as_ref());
+                }
+            }
+            Err(err) if helpers::is_rejected(&err) => {
+                error!("Could not submit queued score(s): {:#}", err);
+                error!(
+                    "Dropping rejected queued score(s): {}",
+                    serde_json::to_string(import).unwrap_or_default()
+                );
+                if instance.main {
+                    history::update_status(import, Status::Rejected, Some(&format!("{:#}", err)), None);
+                }
+            }
+            Err(err) => {
+                error!("Could not submit queued score(s): {:#}", err);
+                warn!("{} score(s) are still queued for {}", total - index, instance);
+                if helpers::is_unauthorized(&err) {
+                    instance.disable();
+                }
+                return Ok(());
+            }
+        }
+
+        instance.queue.remove(entry)?;
+    }
+
+    info!("All queued scores were submitted to {}", instance);
+
+    Ok(())
+}
+
+/// Flushes the queue on a separate thread, its retries wait between attempts and would hold up
+/// the scores played meanwhile
+pub fn flush_in_background(instance: &'static Instance) {
+    std::thread::spawn(move || {
+        if let Err(err) = flush(instance) {
+            error!("{:#}", err);
+        }
+    });
+}
@@ -0,0 +1,187 @@
+use crate::cards;
+use crate::configuration::RuleConfiguration;
+use crate::types::tachi::{ImportMeta, ImportScore};
+use std::fmt;
+
+impl RuleConfiguration {
+    pub fn matches(&self, card: &str, ref_id: &str, meta: &ImportMeta, score: &ImportScore) -> bool {
+        if !self.cards.is_empty()
+            && !self.cards.iter().any(|entry| cards::matches_entry(entry, card, ref_id))
+        {
+            return false;
+        }
+
+        if !self.difficulties.is_empty() && !self.difficulties.contains(&score.difficulty) {
+            return false;
+        }
+
+        if !self.playtypes.is_empty() && !self.playtypes.contains(&meta.play_type) {
+            return false;
+        }
+
+        if !self.lamps.is_empty() && !self.lamps.contains(&score.lamp) {
+            return false;
+        }
+
+        if !self.mcodes.is_empty() && !self.mcodes.iter().any(|m| m.to_string() == score.identifier) {
+            return false;
+        }
+
+        if let Some(score_below) = self.score_below {
+            if score.score >= score_below {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+pub struct RuleName<'a> {
+    index: usize,
+    rule: &'a RuleConfiguration,
+}
+
+impl fmt::Display for RuleName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.rule.name {
+            Some(name) => write!(f, "rule #{} '{}'", self.index + 1, name),
+            None => write!(f, "rule #{}", self.index + 1),
+        }
+    }
+}
+
+/// Returns the first rule matching the given score, if any
+pub fn find_matching_rule<'a>(
+    rules: &'a [RuleConfiguration],
+    card: &str,
+    ref_id: &str,
+    meta: &ImportMeta,
+    score: &ImportScore,
+) -> Option<RuleName<'a>> {
+    rules
+        .iter()
+        .enumerate()
+        .find(|(_, rule)| rule.matches(card, ref_id, meta, score))
+        .map(|(index, rule)| RuleName { index, rule })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::tachi::{Difficulty, HitMeta, Judgements, Optional, Playtype, TachiLamp};
+
+    const CARD: &str = "E004010027A5FC68";
+    const REF_ID: &str = "ABCDEF0123456789";
+
+    fn score(difficulty: Difficulty, lamp: TachiLamp, value: u32) -> ImportScore {
+        ImportScore {
+            score: value,
+            lamp,
+            match_type: "inGameID".to_string(),
+            identifier: "38422".to_string(),
+            difficulty,
+            time_achieved: 0,
+            judgements: Judgements::default(),
+            hit_meta: HitMeta::default(),
+            optional: Optional::default(),
+            fingerprint: String::new(),
+        }
+    }
+
+    fn find(rules: &[RuleConfiguration], card: &str, score: &ImportScore) -> Option<String> {
+        find_matching_rule(rules, card, REF_ID, &ImportMeta::default(), score)
+            .map(|rule| rule.to_string())
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = vec![
+            RuleConfiguration {
+                name: Some("beginner".to_string()),
+                difficulties: vec![Difficulty::Beginner],
+                ..Default::default()
+            },
+            RuleConfiguration {
+                name: Some("low scores".to_string()),
+                score_below: Some(500000),
+                ..Default::default()
+            },
+            RuleConfiguration::default(),
+        ];
+
+        let beginner = score(Difficulty::Beginner, TachiLamp::Failed, 100000);
+        assert_eq!(find(&rules, CARD, &beginner).as_deref(), Some("rule #1 'beginner'"));
+
+        let low = score(Difficulty::Expert, TachiLamp::Failed, 100000);
+        assert_eq!(find(&rules, CARD, &low).as_deref(), Some("rule #2 'low scores'"));
+
+        let high = score(Difficulty::Expert, TachiLamp::Clear, 900000);
+        assert_eq!(find(&rules, CARD, &high).as_deref(), Some("rule #3"));
+    }
+
+    #[test]
+    fn every_condition_must_match() {
+        let rules = vec![RuleConfiguration {
+            difficulties: vec![Difficulty::Expert],
+            playtypes: vec![Playtype::DP],
+            lamps: vec![TachiLamp::Failed],
+            ..Default::default()
+        }];
+
+        // The default import meta is SP
+        let failed = score(Difficulty::Expert, TachiLamp::Failed, 100000);
+        assert_eq!(find(&rules, CARD, &failed), None);
+
+        let meta = ImportMeta {
+            play_type: Playtype::DP,
+            ..Default::default()
+        };
+        assert!(rules[0].matches(CARD, REF_ID, &meta, &failed));
+        let cleared = score(Difficulty::Expert, TachiLamp::Clear, 900000);
+        assert!(!rules[0].matches(CARD, REF_ID, &meta, &cleared));
+    }
+
+    #[test]
+    fn matches_cards_with_wildcards_and_refids() {
+        let rules = vec![
+            RuleConfiguration {
+                name: Some("printed".to_string()),
+                cards: vec!["S6E5-23E3-0ZK7-ML1P".to_string()],
+                ..Default::default()
+            },
+            RuleConfiguration {
+                name: Some("wildcard".to_string()),
+                cards: vec!["e00401*".to_string()],
+                ..Default::default()
+            },
+            RuleConfiguration {
+                name: Some("refid".to_string()),
+                cards: vec!["refid:abcdef*".to_string()],
+                ..Default::default()
+            },
+        ];
+        let score = score(Difficulty::Expert, TachiLamp::Clear, 900000);
+
+        assert_eq!(find(&rules, CARD, &score).as_deref(), Some("rule #1 'printed'"));
+        assert_eq!(find(&rules, "E004019999999999", &score).as_deref(), Some("rule #2 'wildcard'"));
+        assert_eq!(find(&rules, "012E0000DEADBEEF", &score).as_deref(), Some("rule #3 'refid'"));
+        assert_eq!(
+            find_matching_rule(&rules, "012E0000DEADBEEF", "0000", &ImportMeta::default(), &score)
+                .map(|rule| rule.to_string()),
+            None
+        );
+    }
+
+    #[test]
+    fn no_rule_matches() {
+        let score = score(Difficulty::Expert, TachiLamp::Clear, 900000);
+        assert_eq!(find(&[], CARD, &score), None);
+
+        let rules = vec![RuleConfiguration {
+            mcodes: vec![12345],
+            ..Default::default()
+        }];
+        assert_eq!(find(&rules, CARD, &score), None);
+    }
+}
@@ -0,0 +1,255 @@
+use crate::sinks::stage::Stage;
+use crate::sinks::{CardEvent, ScoreEvent};
+use crate::types::tachi::{Flare, ImportScore};
+use crate::{helpers, paths, CONFIGURATION};
+use lazy_static::lazy_static;
+use log::{debug, error, info, warn};
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::{RwLock, RwLockReadGuard};
+use std::time::{Duration, SystemTime};
+
+/// A script of the scripts folder, compiled once and again when the file changes
+#[derive(Clone)]
+struct Script {
+    name: String,
+    modified: SystemTime,
+    ast: AST,
+}
+
+impl Script {
+    fn defines(&self, function: &str) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|metadata| metadata.name == function && metadata.params.len() == 1)
+    }
+
+    fn call(&self, function: &str, argument: Dynamic) -> Option<Dynamic> {
+        if !self.defines(function) {
+            return None;
+        }
+
+        ENGINE
+            .call_fn::<Dynamic>(&mut Scope::new(), &self.ast, function, (argument,))
+            .map_err(|err| error!("Script '{}' failed in {}: {}", self.name, function, err))
+            .ok()
+    }
+}
+
+lazy_static! {
+    static ref ENGINE: Engine = build_engine();
+    static ref SCRIPTS: RwLock<Vec<Script>> = RwLock::new(Vec::new());
+}
+
+fn build_engine() -> Engine {
+    let mut engine = Engine::new();
+
+    // Scripts run on the sinks thread, a script stuck in a loop must not hold up the next scores
+    engine.set_max_operations(1_000_000);
+    engine.on_print(|text| info!("{}", text));
+    engine.on_debug(|text, source, position| {
+        debug!("{} @ {:?} {}", text, source.unwrap_or("script"), position)
+    });
+
+    engine.register_fn("warn", |text: &str| warn!("{}", text));
+    engine.register_fn("write_file", |path: &str, text: &str| {
+        std::fs::write(paths::resolve(path), text)
+            .map_err(|err| error!("Script could not write '{}': {:#}", path, err))
+            .is_ok()
+    });
+    engine.register_fn("append_file", |path: &str, text: &str| {
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(paths::resolve(path))
+            .and_then(|mut file| std::io::Write::write_all(&mut file, text.as_bytes()))
+            .map_err(|err| error!("Script could not append to '{}': {:#}", path, err))
+            .is_ok()
+    });
+    // Sent in the background, so that slow servers do not hold up the next scripts and sinks
+    engine.register_fn("http_post", |url: &str, body: &str| {
+        let (url, body) = (url.to_string(), body.to_string());
+        std::thread::spawn(move || {
+            if let Err(err) = helpers::request_agent()
+                .post(&url)
+                .set("Content-Type", "application/json")
+                .send_string(&body)
+            {
+                error!("Script request failed: {:#}", err);
+            }
+        });
+    });
+
+    engine
+}
+
+fn read_scripts() -> RwLockReadGuard<'static, Vec<Script>> {
+    SCRIPTS.read().unwrap_or_else(|err| {
+        error!("Scripts RwLock is poisoned: {:#}", err);
+        err.into_inner()
+    })
+}
+
+/// Compiles the scripts of the folder which changed since the previous load, keeping the previous
+/// version of scripts which do not compile anymore
+fn load(folder: &Path, previous: &[Script]) -> Vec<Script> {
+    let mut paths = match std::fs::read_dir(folder) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension() == Some(OsStr::new("rhai")))
+            .collect::<Vec<_>>(),
+        Err(_) => Vec::new(),
+    };
+    // Scripts run in the order of their names
+    paths.sort();
+
+    paths
+        .into_iter()
+        .filter_map(|path| {
+            let name = path.file_name()?.to_string_lossy().to_string();
+            let modified = path.metadata().and_then(|metadata| metadata.modified()).ok()?;
+            let old = previous.iter().find(|script| script.name == name);
+            if let Some(old) = old.filter(|old| old.modified == modified) {
+                return Some(old.clone());
+            }
+
+            match ENGINE.compile_file(path.clone()) {
+                Ok(ast) => {
+                    info!("Loaded script '{}'", name);
+                    Some(Script { name, modified, ast })
+                }
+                Err(err) => {
+                    error!("Could not compile script '{}': {}", name, err);
+                    old.cloned()
+                }
+            }
+        })
+        .collect()
+}
+
+fn reload(folder: &Path) {
+    let previous = read_scripts().clone();
+    let scripts = load(folder, &previous);
+    for script in &previous {
+        if !scripts.iter().any(|loaded| loaded.name == script.name) {
+            info!("Unloaded script '{}'", script.name);
+        }
+    }
+
+    match SCRIPTS.write() {
+        Ok(mut guard) => *guard = scripts,
+        Err(err) => error!("Could not acquire write lock on scripts: {:#}", err),
+    }
+}
+
+/// Loads the scripts, then reloads them in the background when files of the folder change
+pub fn init() {
+    let configuration = CONFIGURATION.get();
+    if !configuration.scripts.enable {
+        return;
+    }
+
+    let folder = PathBuf::from(paths::resolve(&configuration.scripts.folder));
+    if !folder.is_dir() {
+        warn!(
+            "Scripts folder '{}' does not exist, scripts added to it will be loaded once it is created",
+            folder.display()
+        );
+    }
+    reload(&folder);
+
+    std::thread::spawn(move || loop {
+        std::thread::sleep(Duration::from_secs(2));
+        reload(&folder);
+    });
+}
+
+fn apply(stage: &mut Stage, score: &mut ImportScore, changes: &Map) {
+    if let Some(value) = changes.get("flare") {
+        let flare = if value.is_unit() {
+            Some(String::new())
+        } else {
+            value.clone().into_string().ok()
+        };
+        match flare.as_deref() {
+            Some("") => {
+                score.optional.flare = Flare::None;
+                stage.flare = None;
+            }
+            Some(flare) => match serde_json::from_value::<Flare>(serde_json::json!(flare)) {
+                Ok(parsed) => {
+                    score.optional.flare = parsed;
+                    stage.flare = Some(flare.to_string());
+                }
+                Err(_) => warn!("Ignoring invalid flare '{}' from script", flare),
+            },
+            None => warn!("Ignoring invalid flare from script: {}", value),
+        }
+    }
+
+    for (field, target, shown) in [
+        ("fast", &mut score.hit_meta.fast, &mut stage.fast),
+        ("slow", &mut score.hit_meta.slow, &mut stage.slow),
+        ("max_combo", &mut score.hit_meta.max_combo, &mut stage.max_combo),
+        ("ex_score", &mut score.hit_meta.ex_score, &mut stage.ex_score),
+    ] {
+        if let Some(value) = changes.get(field) {
+            match value.as_int().ok().and_then(|value| u32::try_from(value).ok()) {
+                Some(value) => {
+                    *target = value;
+                    *shown = value;
+                }
+                None => warn!("Ignoring invalid {} from script: {}", field, value),
+            }
+        }
+    }
+}
+
+/// Gives each score of an event to the `on_score` function of every script, in order
+///
+/// Scripts return `false` to drop the score, or the score with changed optional fields (`flare`,
+/// `fast`, `slow`, `max_combo` and `ex_score`), other fields are only given for context. Returns
+/// the reason to skip the event when a script dropped a score.
+pub fn on_score(event: &mut ScoreEvent) -> Option<String> {
+    let scripts = read_scripts();
+    if !scripts.iter().any(|script| script.defines("on_score")) {
+        return None;
+    }
+
+    for index in 0..event.import.scores.len() {
+        let mut stage = Stage::new(event, &event.import.scores[index]);
+        for script in scripts.iter() {
+            let result = match rhai::serde::to_dynamic(&stage) {
+                Ok(argument) => script.call("on_score", argument),
+                Err(err) => {
+                    error!("Could not give score to script '{}': {}", script.name, err);
+                    None
+                }
+            };
+
+            match result {
+                Some(result) if result.as_bool() == Ok(false) => {
+                    return Some(format!("score was dropped by script '{}'", script.name));
+                }
+                Some(result) => {
+                    if let Some(changes) = result.try_cast::<Map>() {
+                        apply(&mut stage, &mut event.import.scores[index], &changes);
+                    }
+                }
+                None => {}
+            }
+        }
+    }
+
+    None
+}
+
+/// Gives a card event to the `on_card` function of every script
+pub fn on_card(event: &CardEvent) {
+    for script in read_scripts().iter() {
+        if let Ok(argument) = rhai::serde::to_dynamic(event) {
+            script.call("on_card", argument);
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use anyhow::Result;
+use crate::history::Status;
+use crate::sinks::stage::Stage;
+use crate::sinks::{template, Outcome, Report, ScoreEvent, ScoreSink};
+use log::{debug, error, warn};
+use std::io::Write;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Runs an external command for every score, with the score fields as `TAKURE_*` environment
+/// variables and the score as JSON on its standard input
+pub struct CommandSink {
+    command: Vec<String>,
+    timeout: Duration,
+}
+
+impl CommandSink {
+    pub fn new(command: &[String], timeout: u64) -> Self {
+        CommandSink {
+            command: command.to_vec(),
+            timeout: Duration::from_secs(timeout),
+        }
+    }
+}
+
+/// Waits for the command to exit, killing it once the timeout is over
+fn wait(name: &str, mut child: Child, timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) if status.success() => {
+                debug!("{} exited successfully", name);
+                return;
+            }
+            Ok(Some(status)) => {
+                error!("{} exited with {}", name, status);
+                return;
+            }
+            Ok(None) if Instant::now() >= deadline => {
+                warn!("{} did not exit after {}s, killing it", name, timeout.as_secs());
+                if let Err(err) = child.kill().and_then(|_| child.wait()) {
+                    error!("Could not kill {}: {:#}", name, err);
+                }
+                return;
+            }
+            Ok(None) => std::thread::sleep(Duration::from_millis(100)),
+            Err(err) => {
+                error!("Could not wait for {}: {:#}", name, err);
+                return;
+            }
+        }
+    }
+}
+
+impl ScoreSink for CommandSink {
+    fn kind(&self) -> &'static str {
+        "command"
+    }
+
+    fn name(&self) -> String {
+        format!("command '{}'", self.command.join(" "))
+    }
+
+    fn submit(&self, event: &ScoreEvent, _reports: &[Report]) -> Result<Outcome> {
+        let (program, args) = self
+            .command
+            .split_first()
+            .ok_or(anyhow::anyhow!("Command is empty"))?;
+
+        for score in &event.import.scores {
+            let input = serde_json::to_vec(score)?;
+            let fields = template::fields(&Stage::new(event, score));
+
+            let mut child = Command::new(program)
+                .args(args)
+                .envs(fields.iter().map(|(field, value)| {
+                    (format!("TAKURE_{}", field.to_ascii_uppercase()), value)
+                }))
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .spawn()
+                .map_err(|err| anyhow::anyhow!("Could not run '{}': {:#}", program, err))?;
+
+            // Commands can run until their timeout, the next sinks and scores do not wait for them
+            let name = self.name();
+            let timeout = self.timeout;
+            std::thread::spawn(move || {
+                if let Some(mut stdin) = child.stdin.take() {
+                    if let Err(err) = stdin.write_all(&input) {
+                        error!("Could not write score to {}: {:#}", name, err);
+                    }
+                }
+                wait(&name, child, timeout);
+            });
+        }
+
+        Ok(Outcome::new(Status::Submitted))
+    }
+}
@@ -0,0 +1,32 @@
+use anyhow::Result;
+use crate::history::{self, Status};
+use crate::sinks::{Outcome, Report, ScoreEvent, ScoreSink};
+
+/// Records scores in the local score history, with the outcome of the main Tachi instance
+pub struct DatabaseSink;
+
+impl ScoreSink for DatabaseSink {
+    fn kind(&self) -> &'static str {
+        "database"
+    }
+
+    fn receives_skipped(&self) -> bool {
+        true
+    }
+
+    fn receives_reports(&self) -> bool {
+        true
+    }
+
+    fn submit(&self, event: &ScoreEvent, reports: &[Report]) -> Result<Outcome> {
+        let tachi = reports.iter().find(|report| report.main);
+        let (status, reason, response) = match (&event.skip_reason, tachi) {
+            (Some(reason), _) => (Status::Skipped, Some(reason.as_str()), None),
+            (None, Some(report)) => (report.status, report.reason.as_deref(), report.response.as_ref()),
+            (None, None) => (Status::Unsubmitted, None, None),
+        };
+        history::record(&event.card, &event.ref_id, &event.import, status, reason, response);
+
+        Ok(Outcome::new(Status::Submitted))
+    }
+}
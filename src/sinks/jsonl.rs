@@ -0,0 +1,55 @@
+use anyhow::Result;
+use crate::history::Status;
+use crate::paths;
+use crate::sinks::{Outcome, Report, ScoreEvent, ScoreSink};
+use log::error;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::Mutex;
+
+/// Appends every score to a local file, one JSON object per line
+pub struct JsonlSink {
+    path: String,
+    lock: Mutex<()>,
+}
+
+impl JsonlSink {
+    pub fn new(path: &str) -> Self {
+        JsonlSink {
+            path: paths::resolve(path),
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+impl ScoreSink for JsonlSink {
+    fn kind(&self) -> &'static str {
+        "jsonl"
+    }
+
+    fn name(&self) -> String {
+        format!("jsonl '{}'", self.path)
+    }
+
+    fn receives_skipped(&self) -> bool {
+        true
+    }
+
+    fn submit(&self, event: &ScoreEvent, _reports: &[Report]) -> Result<Outcome> {
+        let line = serde_json::to_string(event)?;
+
+        let _guard = self.lock.lock().unwrap_or_else(|err| {
+            error!("JSONL sink Mutex is poisoned: {:#}", err);
+            err.into_inner()
+        });
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|err| anyhow::anyhow!("Could not open '{}': {:#}", self.path, err))?;
+        writeln!(file, "{}", line)
+            .map_err(|err| anyhow::anyhow!("Could not write to '{}': {:#}", self.path, err))?;
+
+        Ok(Outcome::new(Status::Submitted))
+    }
+}
@@ -0,0 +1,463 @@
+mod command;
+mod database;
+mod jsonl;
+mod mqtt;
+mod now_playing;
+mod overlay;
+mod plugin;
+mod session;
+pub mod stage;
+mod tachi;
+mod template;
+mod webhook;
+
+use anyhow::Result;
+use crate::configuration::{Configuration, FailurePolicy, RuleConfiguration, SinkConfiguration, SinkKind};
+use crate::history::Status;
+use crate::types::tachi::Import;
+use crate::{cards, helpers, rules, scripts, CONFIGURATION};
+use lazy_static::lazy_static;
+use log::{error, info, warn};
+use serde::Serialize;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+
+/// A processed score, as given to every sink
+#[derive(Debug, Clone, Serialize)]
+pub struct ScoreEvent {
+    pub card: String,
+    pub ref_id: String,
+    pub import: Import,
+    /// Why the score should not be submitted, according to the card lists and submission rules
+    pub skip_reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CardEventKind {
+    /// A card was scanned
+    CardIn,
+    /// The game of the card ended, or another card was scanned
+    CardOut,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CardEvent {
+    #[serde(rename = "type")]
+    pub kind: CardEventKind,
+    pub card: String,
+    pub konami_id: String,
+}
+
+/// What a sink did with a score
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    pub status: Status,
+    pub response: Option<serde_json::Value>,
+}
+
+impl Outcome {
+    pub fn new(status: Status) -> Self {
+        Outcome {
+            status,
+            response: None,
+        }
+    }
+}
+
+/// Outcome of a sink for a score, given to the next sinks
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// Whether the sink submits to the main Tachi instance
+    pub main: bool,
+    pub status: Status,
+    pub reason: Option<String>,
+    pub response: Option<serde_json::Value>,
+}
+
+pub trait ScoreSink: Send + Sync {
+    /// Type of the sink, as written in the configuration
+    fn kind(&self) -> &'static str;
+
+    /// Name of the sink shown in the log
+    fn name(&self) -> String {
+        self.kind().to_string()
+    }
+
+    /// Whether the sink also receives scores skipped by the card lists or submission rules
+    fn receives_skipped(&self) -> bool {
+        false
+    }
+
+    /// Whether the sink records the outcome of the other sinks, in which case it runs after them
+    fn receives_reports(&self) -> bool {
+        false
+    }
+
+    /// Whether the sink submits to the main Tachi instance, whose outcome is recorded in the
+    /// score history
+    fn is_main(&self) -> bool {
+        false
+    }
+
+    fn submit(&self, event: &ScoreEvent, reports: &[Report]) -> Result<Outcome>;
+
+    /// Receives card scans and game ends, most sinks ignore them
+    fn card(&self, _event: &CardEvent) -> Result<()> {
+        Ok(())
+    }
+
+    /// Keeps a score that could not be submitted, to submit it later
+    fn queue(&self, _event: &ScoreEvent) -> Result<()> {
+        Err(anyhow::anyhow!("{} sink cannot queue scores", self.name()))
+    }
+
+    fn default_policy(&self) -> FailurePolicy {
+        FailurePolicy::Ignore
+    }
+}
+
+#[derive(Clone)]
+struct Sink {
+    inner: Arc<dyn ScoreSink>,
+    policy: FailurePolicy,
+    skip: Vec<RuleConfiguration>,
+    /// Configured in its own section rather than in `[[sinks]]`, kept until the game restarts
+    section: bool,
+}
+
+impl Sink {
+    /// Builds the sinks of a configuration entry, Tachi entries without an instance name giving
+    /// one sink per instance
+    fn build(configuration: &SinkConfiguration) -> Vec<Self> {
+        let inners: Vec<Arc<dyn ScoreSink>> = match &configuration.kind {
+            SinkKind::Tachi { instance: None } => crate::tachi::instances()
+                .into_iter()
+                .map(|instance| Arc::new(tachi::TachiSink::new(instance)) as Arc<dyn ScoreSink>)
+                .collect(),
+            SinkKind::Tachi { instance: Some(name) } => match crate::tachi::find(name) {
+                Some(instance) => vec![Arc::new(tachi::TachiSink::new(instance))],
+                None => {
+                    error!("Tachi sink refers to unknown instance '{}', it will not be used", name);
+                    Vec::new()
+                }
+            },
+            SinkKind::Jsonl { path } => vec![Arc::new(jsonl::JsonlSink::new(path))],
+            SinkKind::Database => vec![Arc::new(database::DatabaseSink)],
+            SinkKind::Webhook { url, template } => {
+                vec![Arc::new(webhook::WebhookSink::new(url, template.clone()))]
+            }
+            SinkKind::Command { command, timeout } => {
+                vec![Arc::new(command::CommandSink::new(command, *timeout))]
+            }
+        };
+
+        inners
+            .into_iter()
+            .map(|inner| Sink {
+                policy: configuration
+                    .on_failure
+                    .unwrap_or_else(|| inner.default_policy()),
+                inner,
+                skip: configuration.skip.clone(),
+                section: false,
+            })
+            .collect()
+    }
+}
+
+fn section_sinks(configuration: &Configuration) -> Vec<Sink> {
+    let mut inners: Vec<Arc<dyn ScoreSink>> = Vec::new();
+    if configuration.overlay.enable {
+        match overlay::OverlaySink::new(&configuration.overlay) {
+            Ok(overlay) => inners.push(Arc::new(overlay)),
+            Err(err) => error!("{:#}", err),
+        }
+    }
+    if configuration.now_playing.enable {
+        inners.push(Arc::new(now_playing::NowPlayingSink::new(&configuration.now_playing)));
+    }
+    if configuration.mqtt.enable {
+        inners.push(Arc::new(mqtt::MqttSink::new(&configuration.mqtt)));
+    }
+    // Modules can register callbacks at any time
+    inners.push(Arc::new(plugin::PluginSink));
+
+    inners
+        .into_iter()
+        .map(|inner| Sink {
+            inner,
+            policy: FailurePolicy::Ignore,
+            skip: Vec::new(),
+            section: true,
+        })
+        .collect()
+}
+
+fn build_sinks(configuration: &Configuration, sections: Vec<Sink>) -> Vec<Sink> {
+    let configurations = if configuration.sinks.is_empty() {
+        let mut kinds = vec![SinkKind::Tachi { instance: None }];
+        if configuration.history.enable {
+            kinds.push(SinkKind::Database);
+        }
+        kinds
+            .into_iter()
+            .map(|kind| SinkConfiguration {
+                kind,
+                on_failure: None,
+                skip: Vec::new(),
+            })
+            .collect()
+    } else {
+        configuration.sinks.clone()
+    };
+
+    let mut sinks = configurations.iter().flat_map(Sink::build).collect::<Vec<_>>();
+    sinks.extend(sections);
+
+    // Stable sort, sinks keep their order otherwise
+    sinks.sort_by_key(|sink| sink.inner.receives_reports());
+
+    if !configuration.history.enable
+        && sinks.iter().any(|sink| sink.inner.kind() == "database")
+    {
+        warn!("A database sink is configured but the score history is disabled, it will not record anything");
+    }
+
+    sinks
+}
+
+lazy_static! {
+    static ref SINKS: RwLock<Arc<Vec<Sink>>> = {
+        let configuration = CONFIGURATION.get();
+        RwLock::new(Arc::new(build_sinks(&configuration, section_sinks(&configuration))))
+    };
+    /// Card currently in, to only send card out events for cards which are in
+    static ref CARD_IN: Mutex<Option<String>> = Mutex::new(None);
+    static ref WORKER: Mutex<Sender<Job>> = Mutex::new(spawn_worker());
+}
+
+/// Events given to the sinks, in the order they happened
+enum Job {
+    Card(CardEvent),
+    Score(ScoreEvent),
+}
+
+/// Starts the thread running the sinks, so that the game never waits for them
+fn spawn_worker() -> Sender<Job> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        for job in receiver {
+            match job {
+                Job::Card(event) => run_card(&event),
+                Job::Score(mut event) => {
+                    check(&mut event);
+                    run(&event);
+                }
+            }
+        }
+    });
+
+    sender
+}
+
+fn send(job: Job) {
+    let sender = WORKER.lock().unwrap_or_else(|err| {
+        error!("Sinks worker Mutex is poisoned: {:#}", err);
+        err.into_inner()
+    });
+    if sender.send(job).is_err() {
+        error!("Sinks worker is not running, event is lost");
+    }
+}
+
+/// Builds the sinks, starting the ones that run in the background
+pub fn init() {
+    lazy_static::initialize(&SINKS);
+    lazy_static::initialize(&WORKER);
+}
+
+fn sinks() -> Arc<Vec<Sink>> {
+    SINKS
+        .read()
+        .map(|sinks| sinks.clone())
+        .unwrap_or_else(|err| err.into_inner().clone())
+}
+
+/// Rebuilds the sinks configured in `[[sinks]]` after a configuration change
+pub fn reload(configuration: &Configuration) {
+    let sections = sinks().iter().filter(|sink| sink.section).cloned().collect();
+    let sinks = Arc::new(build_sinks(configuration, sections));
+    match SINKS.write() {
+        Ok(mut guard) => *guard = sinks,
+        Err(err) => *err.into_inner() = sinks,
+    }
+}
+
+fn dispatch_card(kind: CardEventKind, card: &str) {
+    send(Job::Card(CardEvent {
+        kind,
+        card: card.to_string(),
+        konami_id: cards::to_konami_id(card).unwrap_or_default(),
+    }));
+}
+
+fn run_card(event: &CardEvent) {
+    scripts::on_card(event);
+    for sink in sinks().iter() {
+        if let Err(err) = sink.inner.card(event) {
+            error!("{} sink: {:#}", sink.inner.name(), err);
+        }
+    }
+}
+
+fn lock_card_in() -> MutexGuard<'static, Option<String>> {
+    CARD_IN.lock().unwrap_or_else(|err| {
+        error!("Card Mutex is poisoned: {:#}", err);
+        err.into_inner()
+    })
+}
+
+/// Gives a card scan to every sink, after the card out of the previous card if it is still in
+pub fn card_in(card: &str) {
+    let mut guard = lock_card_in();
+    match guard.as_deref() {
+        Some(previous) if previous == card => return,
+        Some(previous) => dispatch_card(CardEventKind::CardOut, previous),
+        None => {}
+    }
+    dispatch_card(CardEventKind::CardIn, card);
+    *guard = Some(card.to_string());
+}
+
+/// Gives the end of the game of a card to every sink
+pub fn card_out(card: &str) {
+    let mut guard = lock_card_in();
+    if guard.as_deref() == Some(card) {
+        dispatch_card(CardEventKind::CardOut, card);
+        *guard = None;
+    }
+}
+
+/// Gives a processed score to the scripts and rules, then to every configured sink in order, on
+/// the sinks thread
+pub fn dispatch(event: ScoreEvent) {
+    send(Job::Score(event));
+}
+
+/// Gives the score to the scripts, then sets why it is skipped if a script dropped it or if it
+/// matches a submission rule
+fn check(event: &mut ScoreEvent) {
+    // Scripts run before the rules, so that rules see the scores they changed
+    let configuration = CONFIGURATION.get();
+    event.skip_reason = scripts::on_score(event).or_else(|| {
+        event
+            .import
+            .scores
+            .iter()
+            .find_map(|score| {
+                rules::find_matching_rule(&configuration.rules, &event.card, &event.ref_id, &event.import.meta, score)
+            })
+            .map(|rule| format!("score matched {}", rule))
+    });
+}
+
+fn run(event: &ScoreEvent) {
+    if let Some(reason) = &event.skip_reason {
+        info!(
+            "Card {}: {}, skipping score(s) submission",
+            cards::Card(&event.card),
+            reason
+        );
+    } else {
+        for score in &event.import.scores {
+            session::record(&stage::Stage::new(event, score));
+        }
+    }
+
+    let mut reports = Vec::<Report>::new();
+    for sink in sinks().iter() {
+        if event.skip_reason.is_some() && !sink.inner.receives_skipped() {
+            continue;
+        }
+
+        let name = sink.inner.name();
+        let main = sink.inner.is_main();
+        if let Some(rule) = event.import.scores.iter().find_map(|score| {
+            rules::find_matching_rule(&sink.skip, &event.card, &event.ref_id, &event.import.meta, score)
+        }) {
+            let reason = format!("score matched {}", rule);
+            info!("Skipping {} sink: {}", name, reason);
+            reports.push(Report {
+                main,
+                status: Status::Skipped,
+                reason: Some(reason),
+                response: None,
+            });
+            continue;
+        }
+
+        let err = match sink.inner.submit(event, &reports) {
+            Ok(outcome) => {
+                reports.push(Report {
+                    main,
+                    status: outcome.status,
+                    reason: None,
+                    response: outcome.response,
+                });
+                continue;
+            }
+            Err(err) => err,
+        };
+
+        error!("{} sink: {:#}", name, err);
+        let reason = Some(format!("{:#}", err));
+        if helpers::is_rejected(&err) {
+            error!(
+                "Score(s) for card {} were rejected by {} sink and will not be queued: {}",
+                cards::Card(&event.card),
+                name,
+                serde_json::to_string(&event.import).unwrap_or_default()
+            );
+            reports.push(Report {
+                main,
+                status: Status::Rejected,
+                reason,
+                response: None,
+            });
+            if sink.policy == FailurePolicy::Stop {
+                break;
+            }
+            continue;
+        }
+
+        let status = match sink.policy {
+            FailurePolicy::Queue => match sink.inner.queue(event) {
+                Ok(()) => {
+                    warn!(
+                        "Queued score(s) for card {} for later submission to {} sink",
+                        cards::Card(&event.card),
+                        name
+                    );
+                    Status::Queued
+                }
+                Err(err) => {
+                    error!("{:#}", err);
+                    Status::Failed
+                }
+            },
+            FailurePolicy::Ignore | FailurePolicy::Stop => Status::Failed,
+        };
+        reports.push(Report {
+            main,
+            status,
+            reason,
+            response: None,
+        });
+
+        if sink.policy == FailurePolicy::Stop {
+            warn!("{} sink failed, skipping the next sinks", name);
+            break;
+        }
+    }
+}
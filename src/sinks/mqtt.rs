@@ -0,0 +1,103 @@
+use anyhow::Result;
+use crate::configuration::MqttConfiguration;
+use crate::history::Status;
+use crate::sinks::stage::Stage;
+use crate::sinks::{CardEvent, Outcome, Report, ScoreEvent, ScoreSink};
+use log::{debug, error, info, warn};
+use rumqttc::{Client, Event, MqttOptions, Packet, QoS};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+/// Publishes card events and stage results to a MQTT broker
+///
+/// - `card_topic` receives `{"type": "card_in" | "card_out", "card": ..., "konami_id": ...}`
+/// - `stage_topic` receives each stage result, with the same fields as templates
+/// - `last_topic` receives the last stage result as a retained message
+pub struct MqttSink {
+    address: String,
+    configuration: MqttConfiguration,
+    client: Mutex<Client>,
+}
+
+impl MqttSink {
+    pub fn new(configuration: &MqttConfiguration) -> Self {
+        let address = format!("{}:{}", configuration.host, configuration.port);
+        let mut options = MqttOptions::new(
+            configuration.client_id.clone(),
+            configuration.host.clone(),
+            configuration.port,
+        );
+        options.set_keep_alive(Duration::from_secs(30));
+        if !configuration.username.is_empty() {
+            options.set_credentials(configuration.username.clone(), configuration.password.clone());
+        }
+
+        // Messages published while the broker is unreachable wait in the client, up to this many
+        let (client, mut connection) = Client::new(options, 64);
+        let broker = address.clone();
+        std::thread::spawn(move || {
+            // Iterating drives the connection, and reconnects after errors
+            for notification in connection.iter() {
+                match notification {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        info!("Connected to MQTT broker '{}'", broker)
+                    }
+                    Ok(_) => {}
+                    Err(err) => {
+                        warn!("MQTT broker '{}' is unreachable: {}", broker, err);
+                        std::thread::sleep(Duration::from_secs(5));
+                    }
+                }
+            }
+        });
+
+        MqttSink {
+            address,
+            configuration: configuration.clone(),
+            client: Mutex::new(client),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Client> {
+        self.client.lock().unwrap_or_else(|err| {
+            error!("MQTT client Mutex is poisoned: {:#}", err);
+            err.into_inner()
+        })
+    }
+
+    /// Queues a message without waiting for the broker, so that the game is never held up
+    fn publish(&self, topic: &str, retain: bool, payload: Vec<u8>) -> Result<()> {
+        if topic.is_empty() {
+            return Ok(());
+        }
+
+        self.lock()
+            .try_publish(topic, QoS::AtLeastOnce, retain, payload)
+            .map_err(|err| anyhow::anyhow!("Could not publish to '{}': {}", topic, err))
+    }
+}
+
+impl ScoreSink for MqttSink {
+    fn kind(&self) -> &'static str {
+        "mqtt"
+    }
+
+    fn name(&self) -> String {
+        format!("mqtt '{}'", self.address)
+    }
+
+    fn submit(&self, event: &ScoreEvent, _reports: &[Report]) -> Result<Outcome> {
+        for score in &event.import.scores {
+            let payload = serde_json::to_vec(&Stage::new(event, score))?;
+            self.publish(&self.configuration.stage_topic, false, payload.clone())?;
+            self.publish(&self.configuration.last_topic, true, payload)?;
+        }
+
+        Ok(Outcome::new(Status::Submitted))
+    }
+
+    fn card(&self, event: &CardEvent) -> Result<()> {
+        debug!("Publishing {:?} to MQTT", event.kind);
+        self.publish(&self.configuration.card_topic, false, serde_json::to_vec(event)?)
+    }
+}
@@ -0,0 +1,127 @@
+use anyhow::Result;
+use crate::configuration::NowPlayingConfiguration;
+use crate::history::Status;
+use crate::sinks::session::{self, Session};
+use crate::sinks::stage::Stage;
+use crate::sinks::{template, Outcome, Report, ScoreEvent, ScoreSink};
+use crate::{paths, tachi};
+use log::warn;
+use serde_json::json;
+
+/// Fields available in text file templates, on top of the stage fields
+const EXTRA_FIELDS: &[&str] = &[
+    "player",
+    "session_plays",
+    "session_clears",
+    "session_full_combos",
+    "session_personal_bests",
+    "session_average_score",
+    "session_ex_score",
+];
+
+/// Rewrites files with the last stage result and the session totals after each stage, for OBS
+/// text sources and other tools reading files
+pub struct NowPlayingSink {
+    configuration: NowPlayingConfiguration,
+}
+
+impl NowPlayingSink {
+    pub fn new(configuration: &NowPlayingConfiguration) -> Self {
+        for text in &configuration.text {
+            let unknown = template::unknown_fields(&text.template, EXTRA_FIELDS);
+            if !unknown.is_empty() {
+                warn!(
+                    "Template of '{}' has unknown field(s) '{}', they will be written as is",
+                    text.path,
+                    unknown.join("', '")
+                );
+            }
+        }
+
+        NowPlayingSink {
+            configuration: configuration.clone(),
+        }
+    }
+
+    fn write(&self, stage: &Stage, session: &Session) -> Result<()> {
+        let player = tachi::main()
+            .and_then(|instance| instance.username())
+            .unwrap_or_default();
+
+        if !self.configuration.json.is_empty() {
+            let mut totals = serde_json::to_value(session)?;
+            if let Some(totals) = totals.as_object_mut() {
+                totals.remove("stages");
+            }
+            let content = json!({
+                "player": player,
+                "now": stage.without_player(),
+                "session": totals,
+            });
+            write_atomically(&paths::resolve(&self.configuration.json), &serde_json::to_string_pretty(&content)?)?;
+        }
+
+        let mut fields = template::fields(stage);
+        fields.extend(extra_fields(&player, session));
+        for text in &self.configuration.text {
+            let content = template::render(&text.template, &fields, str::to_string);
+            write_atomically(&paths::resolve(&text.path), &content)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes a file next to the destination then renames it, so that readers never see it half
+/// written
+fn write_atomically(path: &str, content: &str) -> Result<()> {
+    let temporary = format!("{}.tmp", path);
+    std::fs::write(&temporary, content)
+        .map_err(|err| anyhow::anyhow!("Could not write '{}': {:#}", temporary, err))?;
+
+    // Readers might briefly lock the destination on Windows
+    let mut attempts = 0;
+    loop {
+        match std::fs::rename(&temporary, path) {
+            Ok(()) => return Ok(()),
+            Err(_) if attempts < 5 => {
+                attempts += 1;
+                std::thread::sleep(std::time::Duration::from_millis(50));
+            }
+            Err(err) => {
+                let _ = std::fs::remove_file(&temporary);
+                return Err(anyhow::anyhow!("Could not replace '{}': {:#}", path, err));
+            }
+        }
+    }
+}
+
+fn extra_fields(player: &str, session: &Session) -> Vec<(&'static str, String)> {
+    vec![
+        ("player", player.to_string()),
+        ("session_plays", session.plays.to_string()),
+        ("session_clears", session.clears.to_string()),
+        ("session_full_combos", session.full_combos.to_string()),
+        ("session_personal_bests", session.personal_bests.to_string()),
+        ("session_average_score", session.average_score.to_string()),
+        ("session_ex_score", session.ex_score.to_string()),
+    ]
+}
+
+impl ScoreSink for NowPlayingSink {
+    fn kind(&self) -> &'static str {
+        "now_playing"
+    }
+
+    fn submit(&self, event: &ScoreEvent, _reports: &[Report]) -> Result<Outcome> {
+        // The session already contains the stages of the event, stored without the player, whose
+        // card can be written to local files
+        if let Some(session) = session::current() {
+            if let Some(stage) = session.stages.last() {
+                self.write(&stage.with_player(event), &session)?;
+            }
+        }
+
+        Ok(Outcome::new(Status::Submitted))
+    }
+}
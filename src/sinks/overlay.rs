@@ -0,0 +1,191 @@
+use anyhow::Result;
+use crate::configuration::OverlayConfiguration;
+use crate::history::Status;
+use crate::sinks::{session, Outcome, Report, ScoreEvent, ScoreSink};
+use log::{debug, error, info, warn};
+use serde_json::json;
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use tungstenite::{Message, WebSocket};
+
+/// Messages waiting to be sent by the thread of each WebSocket client
+type Clients = Arc<Mutex<Vec<Sender<String>>>>;
+
+/// Serves the last stage result and the session over HTTP, and pushes each stage result to
+/// WebSocket clients, for stream overlays
+///
+/// - `GET /now` returns the last stage result
+/// - `GET /session` returns the current session
+/// - WebSocket connections receive `{"type": "hello", ...}` once, then `{"type": "stage", ...}`
+///   after each stage, both with `now` and `session`
+///
+/// Cards and refids are never part of the payloads, overlays are shown on stream.
+pub struct OverlaySink {
+    address: String,
+    clients: Clients,
+}
+
+fn lock(clients: &Clients) -> MutexGuard<'_, Vec<Sender<String>>> {
+    clients.lock().unwrap_or_else(|err| {
+        error!("Overlay clients Mutex is poisoned: {:#}", err);
+        err.into_inner()
+    })
+}
+
+fn state(kind: &str) -> String {
+    json!({
+        "type": kind,
+        "now": session::last_stage(),
+        "session": session::current(),
+    })
+    .to_string()
+}
+
+impl OverlaySink {
+    pub fn new(configuration: &OverlayConfiguration) -> Result<Self> {
+        let address = format!("{}:{}", configuration.bind, configuration.port);
+        let listener = TcpListener::bind(&address)
+            .map_err(|err| anyhow::anyhow!("Could not start overlay server on {}: {:#}", address, err))?;
+        info!("Overlay server listening on http://{}", address);
+
+        let clients = Clients::default();
+        let accepted = clients.clone();
+        let allowed_origin = configuration.allowed_origin.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let clients = accepted.clone();
+                        let allowed_origin = allowed_origin.clone();
+                        std::thread::spawn(move || {
+                            if let Err(err) = handle(stream, &clients, &allowed_origin) {
+                                debug!("Overlay connection error: {:#}", err);
+                            }
+                        });
+                    }
+                    Err(err) => warn!("Could not accept overlay connection: {:#}", err),
+                }
+            }
+        });
+
+        Ok(OverlaySink { address, clients })
+    }
+}
+
+/// Reads the request head without consuming it, so that WebSocket handshakes can still be read
+///
+/// Returns the head and its length in bytes.
+fn peek_head(stream: &TcpStream) -> Result<(String, usize)> {
+    let mut buffer = [0u8; 4096];
+    for _ in 0..50 {
+        let length = stream.peek(&mut buffer)?;
+        if let Some(end) = buffer[..length].windows(4).position(|window| window == b"\r\n\r\n") {
+            return Ok((String::from_utf8_lossy(&buffer[..end]).to_string(), end + 4));
+        }
+        if length == 0 || length == buffer.len() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+
+    Err(anyhow::anyhow!("Incomplete request"))
+}
+
+/// Answers pings and close frames of a WebSocket client, and sends it the messages of its
+/// channel, until either side goes away
+fn serve(mut socket: WebSocket<TcpStream>, messages: Receiver<String>) -> Result<()> {
+    socket.get_ref().set_read_timeout(Some(Duration::from_millis(100)))?;
+    socket.send(Message::Text(state("hello")))?;
+
+    loop {
+        match socket.read() {
+            // Pongs and close replies are sent by the next read
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(err))
+                if matches!(err.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => {}
+            Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+            Err(err) => return Err(err.into()),
+        }
+
+        match messages.try_recv() {
+            Ok(message) => socket.send(Message::Text(message))?,
+            Err(TryRecvError::Empty) => {}
+            Err(TryRecvError::Disconnected) => return Ok(()),
+        }
+    }
+}
+
+fn handle(mut stream: TcpStream, clients: &Clients, allowed_origin: &str) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(1)))?;
+
+    let (head, length) = peek_head(&stream)?;
+    if head.to_ascii_lowercase().contains("upgrade: websocket") {
+        let socket = tungstenite::accept(stream)
+            .map_err(|err| anyhow::anyhow!("WebSocket handshake failed: {}", err))?;
+        let (sender, receiver) = mpsc::channel();
+        lock(clients).push(sender);
+        return serve(socket, receiver);
+    }
+
+    let path = head
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .unwrap_or("/");
+    let (status, body) = match path.split('?').next().unwrap_or(path) {
+        "/now" => ("200 OK", serde_json::to_string(&session::last_stage())?),
+        "/session" => ("200 OK", serde_json::to_string(&session::current())?),
+        _ => ("404 Not Found", json!({ "error": "not found" }).to_string()),
+    };
+
+    // The request is only peeked, reading it keeps clients from seeing a reset connection
+    let mut request = vec![0u8; length];
+    std::io::Read::read_exact(&mut stream, &mut request)?;
+    let cors = if allowed_origin.is_empty() {
+        String::new()
+    } else {
+        format!("Access-Control-Allow-Origin: {}\r\n", allowed_origin)
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        cors,
+        body
+    )?;
+
+    Ok(())
+}
+
+impl ScoreSink for OverlaySink {
+    fn kind(&self) -> &'static str {
+        "overlay"
+    }
+
+    fn name(&self) -> String {
+        format!("overlay '{}'", self.address)
+    }
+
+    fn submit(&self, event: &ScoreEvent, _reports: &[Report]) -> Result<Outcome> {
+        if event.import.scores.is_empty() {
+            return Ok(Outcome::new(Status::Submitted));
+        }
+
+        // The session already contains the stages of the event
+        let message = state("stage");
+        lock(&self.clients).retain(|client| match client.send(message.clone()) {
+            Ok(()) => true,
+            Err(_) => {
+                debug!("Dropping disconnected overlay client");
+                false
+            }
+        });
+
+        Ok(Outcome::new(Status::Submitted))
+    }
+}
@@ -0,0 +1,286 @@
+use anyhow::Result;
+use crate::history::Status;
+use crate::plugin::{self, TakureEvent};
+use crate::sinks::stage::Stage;
+use crate::sinks::{CardEvent, CardEventKind, Outcome, Report, ScoreEvent, ScoreSink};
+use std::ffi::{c_char, CString};
+
+/// Gives card events and stage results to the callbacks registered through the C ABI
+pub struct PluginSink;
+
+/// Strings given to callbacks, kept alive until they return
+#[derive(Default)]
+struct Strings(Vec<CString>);
+
+impl Strings {
+    fn add(&mut self, value: &str) -> *const c_char {
+        // Strings from the game or Tachi never contain nul bytes, they are dropped just in case
+        let value = CString::new(value.replace('\0', "")).unwrap_or_default();
+        let pointer = value.as_ptr();
+        self.0.push(value);
+        pointer
+    }
+
+    fn add_optional(&mut self, value: Option<&str>) -> *const c_char {
+        value.map_or(std::ptr::null(), |value| self.add(value))
+    }
+}
+
+fn empty_event(kind: u32) -> TakureEvent {
+    TakureEvent {
+        version: plugin::ABI_VERSION,
+        size: std::mem::size_of::<TakureEvent>() as u32,
+        kind,
+        card: std::ptr::null(),
+        konami_id: std::ptr::null(),
+        title: std::ptr::null(),
+        mcode: std::ptr::null(),
+        playtype: std::ptr::null(),
+        difficulty: std::ptr::null(),
+        lamp: std::ptr::null(),
+        flare: std::ptr::null(),
+        score: 0,
+        ex_score: 0,
+        max_combo: 0,
+        marvelous: 0,
+        perfect: 0,
+        great: 0,
+        good: 0,
+        ok: 0,
+        miss: 0,
+        fast: 0,
+        slow: 0,
+        skip_reason: std::ptr::null(),
+    }
+}
+
+/// Builds the event of a stage result, whose strings live in `strings`
+fn stage_event(stage: &Stage, strings: &mut Strings) -> TakureEvent {
+    TakureEvent {
+        card: strings.add(&stage.card),
+        konami_id: strings.add(&stage.konami_id),
+        title: strings.add(&stage.title),
+        mcode: strings.add(&stage.mcode),
+        playtype: strings.add(&stage.playtype),
+        difficulty: strings.add(&stage.difficulty),
+        lamp: strings.add(&stage.lamp),
+        flare: strings.add_optional(stage.flare.as_deref()),
+        score: stage.score,
+        ex_score: stage.ex_score,
+        max_combo: stage.max_combo,
+        marvelous: stage.marvelous,
+        perfect: stage.perfect,
+        great: stage.great,
+        good: stage.good,
+        ok: stage.ok,
+        miss: stage.miss,
+        fast: stage.fast,
+        slow: stage.slow,
+        skip_reason: strings.add_optional(stage.skip_reason.as_deref()),
+        ..empty_event(plugin::EVENT_SCORE)
+    }
+}
+
+impl ScoreSink for PluginSink {
+    fn kind(&self) -> &'static str {
+        "plugin"
+    }
+
+    fn receives_skipped(&self) -> bool {
+        true
+    }
+
+    fn submit(&self, event: &ScoreEvent, _reports: &[Report]) -> Result<Outcome> {
+        if !plugin::has_callbacks() {
+            return Ok(Outcome::new(Status::Submitted));
+        }
+
+        for score in &event.import.scores {
+            let mut strings = Strings::default();
+            plugin::emit(&stage_event(&Stage::new(event, score), &mut strings));
+        }
+
+        Ok(Outcome::new(Status::Submitted))
+    }
+
+    fn card(&self, event: &CardEvent) -> Result<()> {
+        if !plugin::has_callbacks() {
+            return Ok(());
+        }
+
+        let mut strings = Strings::default();
+        let kind = match event.kind {
+            CardEventKind::CardIn => plugin::EVENT_CARD_IN,
+            CardEventKind::CardOut => plugin::EVENT_CARD_OUT,
+        };
+        plugin::emit(&TakureEvent {
+            card: strings.add(&event.card),
+            konami_id: strings.add(&event.konami_id),
+            ..empty_event(kind)
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::{c_void, CStr};
+    use std::mem::{offset_of, size_of};
+    use std::sync::Mutex;
+
+    /// Fields of an event read by the callback, as another module would
+    #[derive(Debug, Default, PartialEq)]
+    struct Received {
+        version: u32,
+        size: u32,
+        kind: u32,
+        card: Option<String>,
+        title: Option<String>,
+        difficulty: Option<String>,
+        flare: Option<String>,
+        score: u32,
+        miss: u32,
+        slow: u32,
+        skip_reason: Option<String>,
+    }
+
+    fn read(pointer: *const c_char) -> Option<String> {
+        if pointer.is_null() {
+            return None;
+        }
+
+        Some(unsafe { CStr::from_ptr(pointer) }.to_string_lossy().into_owned())
+    }
+
+    extern "C" fn callback(event: *const TakureEvent, user_data: *mut c_void) {
+        let received = unsafe { &*(user_data as *const Mutex<Vec<Received>>) };
+        let event = unsafe { &*event };
+        received.lock().unwrap().push(Received {
+            version: event.version,
+            size: event.size,
+            kind: event.kind,
+            card: read(event.card),
+            title: read(event.title),
+            difficulty: read(event.difficulty),
+            flare: read(event.flare),
+            score: event.score,
+            miss: event.miss,
+            slow: event.slow,
+            skip_reason: read(event.skip_reason),
+        });
+    }
+
+    fn stage() -> Stage {
+        Stage {
+            card: "E004010027A5FC68".to_string(),
+            konami_id: "S6E523E30ZK7ML1P".to_string(),
+            ref_id: "ABCDEF0123456789".to_string(),
+            title: "MAX 300".to_string(),
+            mcode: "38422".to_string(),
+            playtype: "SP".to_string(),
+            difficulty: "EXPERT".to_string(),
+            score: 987650,
+            ex_score: 600,
+            lamp: "CLEAR".to_string(),
+            flare: None,
+            max_combo: 200,
+            marvelous: 180,
+            perfect: 15,
+            great: 5,
+            good: 0,
+            miss: 2,
+            ok: 20,
+            fast: 3,
+            slow: 4,
+            pb: None,
+            pb_delta: None,
+            time: String::new(),
+            skip_reason: Some("card is not whitelisted".to_string()),
+        }
+    }
+
+    #[test]
+    fn keeps_the_event_layout() {
+        // Offsets of the version 1 event on 64-bit and 32-bit targets, as in include/takure.h
+        let offsets = [
+            ("version", offset_of!(TakureEvent, version), 0, 0),
+            ("size", offset_of!(TakureEvent, size), 4, 4),
+            ("kind", offset_of!(TakureEvent, kind), 8, 8),
+            ("card", offset_of!(TakureEvent, card), 16, 12),
+            ("konami_id", offset_of!(TakureEvent, konami_id), 24, 16),
+            ("title", offset_of!(TakureEvent, title), 32, 20),
+            ("mcode", offset_of!(TakureEvent, mcode), 40, 24),
+            ("playtype", offset_of!(TakureEvent, playtype), 48, 28),
+            ("difficulty", offset_of!(TakureEvent, difficulty), 56, 32),
+            ("lamp", offset_of!(TakureEvent, lamp), 64, 36),
+            ("flare", offset_of!(TakureEvent, flare), 72, 40),
+            ("score", offset_of!(TakureEvent, score), 80, 44),
+            ("ex_score", offset_of!(TakureEvent, ex_score), 84, 48),
+            ("max_combo", offset_of!(TakureEvent, max_combo), 88, 52),
+            ("marvelous", offset_of!(TakureEvent, marvelous), 92, 56),
+            ("perfect", offset_of!(TakureEvent, perfect), 96, 60),
+            ("great", offset_of!(TakureEvent, great), 100, 64),
+            ("good", offset_of!(TakureEvent, good), 104, 68),
+            ("ok", offset_of!(TakureEvent, ok), 108, 72),
+            ("miss", offset_of!(TakureEvent, miss), 112, 76),
+            ("fast", offset_of!(TakureEvent, fast), 116, 80),
+            ("slow", offset_of!(TakureEvent, slow), 120, 84),
+            ("skip_reason", offset_of!(TakureEvent, skip_reason), 128, 88),
+            ("end", size_of::<TakureEvent>(), 136, 92),
+        ];
+
+        let is_64_bit = cfg!(target_pointer_width = "64");
+        for (field, offset, offset_64, offset_32) in offsets {
+            let expected = if is_64_bit { offset_64 } else { offset_32 };
+            assert_eq!(offset, expected, "offset of {}", field);
+        }
+    }
+
+    #[test]
+    fn gives_events_to_callbacks() {
+        let size = size_of::<TakureEvent>();
+
+        let received = Mutex::new(Vec::<Received>::new());
+        let handle = plugin::takure_register_callback(Some(callback), &received as *const _ as *mut c_void);
+
+        PluginSink
+            .card(&CardEvent {
+                kind: CardEventKind::CardIn,
+                card: "E004010027A5FC68".to_string(),
+                konami_id: "S6E523E30ZK7ML1P".to_string(),
+            })
+            .unwrap();
+        let mut strings = Strings::default();
+        plugin::emit(&stage_event(&stage(), &mut strings));
+        assert_eq!(plugin::takure_unregister_callback(handle), 0);
+
+        let received = received.into_inner().unwrap();
+        assert_eq!(
+            received,
+            vec![
+                Received {
+                    version: plugin::ABI_VERSION,
+                    size: size as u32,
+                    kind: plugin::EVENT_CARD_IN,
+                    card: Some("E004010027A5FC68".to_string()),
+                    ..Default::default()
+                },
+                Received {
+                    version: plugin::ABI_VERSION,
+                    size: size as u32,
+                    kind: plugin::EVENT_SCORE,
+                    card: Some("E004010027A5FC68".to_string()),
+                    title: Some("MAX 300".to_string()),
+                    difficulty: Some("EXPERT".to_string()),
+                    flare: None,
+                    score: 987650,
+                    miss: 2,
+                    slow: 4,
+                    skip_reason: Some("card is not whitelisted".to_string()),
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use crate::sinks::stage::Stage;
+use lazy_static::lazy_static;
+use log::{error, info};
+use serde::Serialize;
+use std::sync::{Mutex, MutexGuard};
+
+/// Stages played with the same card since the game started, or since another card was used
+#[derive(Debug, Clone, Serialize)]
+pub struct Session {
+    /// Not serialized, sessions are shown on stream
+    #[serde(skip)]
+    pub card: String,
+    pub started: String,
+    pub plays: u32,
+    pub clears: u32,
+    pub full_combos: u32,
+    pub personal_bests: u32,
+    pub average_score: u32,
+    pub ex_score: u64,
+    pub stages: Vec<Stage>,
+}
+
+lazy_static! {
+    static ref SESSION: Mutex<Option<Session>> = Mutex::new(None);
+}
+
+fn lock() -> MutexGuard<'static, Option<Session>> {
+    SESSION.lock().unwrap_or_else(|err| {
+        error!("Session Mutex is poisoned: {:#}", err);
+        err.into_inner()
+    })
+}
+
+/// Adds a stage to the session of its card, starting a new session if the card changed
+pub fn record(stage: &Stage) {
+    let mut guard = lock();
+    let session = match guard.as_mut() {
+        Some(session) if session.card == stage.card => session,
+        _ => {
+            info!("Starting a new session for card {}", crate::cards::Card(&stage.card));
+            guard.insert(Session {
+                card: stage.card.clone(),
+                started: stage.time.clone(),
+                plays: 0,
+                clears: 0,
+                full_combos: 0,
+                personal_bests: 0,
+                average_score: 0,
+                ex_score: 0,
+                stages: Vec::new(),
+            })
+        }
+    };
+
+    session.plays += 1;
+    if stage.lamp != "FAILED" {
+        session.clears += 1;
+    }
+    if stage.lamp.ends_with("FULL COMBO") {
+        session.full_combos += 1;
+    }
+    if stage.is_personal_best() {
+        session.personal_bests += 1;
+    }
+    session.ex_score += stage.ex_score as u64;
+    session.stages.push(stage.without_player());
+    session.average_score = (session.stages.iter().map(|stage| stage.score as u64).sum::<u64>()
+        / session.stages.len() as u64) as u32;
+}
+
+pub fn current() -> Option<Session> {
+    lock().clone()
+}
+
+pub fn last_stage() -> Option<Stage> {
+    lock().as_ref().and_then(|session| session.stages.last().cloned())
+}
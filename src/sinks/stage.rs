@@ -0,0 +1,112 @@
+use crate::sinks::ScoreEvent;
+use crate::types::tachi::{Flare, ImportScore};
+use crate::{cards, history, music};
+use serde::Serialize;
+
+/// A stage result with everything overlays and integrations usually show, built from a score of
+/// an event
+///
+/// The previous personal best comes from the score history, and is not set if it is disabled or
+/// if the chart was never played.
+#[derive(Debug, Clone, Serialize)]
+pub struct Stage {
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub card: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub konami_id: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub ref_id: String,
+    pub title: String,
+    pub mcode: String,
+    pub playtype: String,
+    pub difficulty: String,
+    pub score: u32,
+    pub ex_score: u32,
+    pub lamp: String,
+    pub flare: Option<String>,
+    pub max_combo: u32,
+    pub marvelous: u32,
+    pub perfect: u32,
+    pub great: u32,
+    pub good: u32,
+    pub miss: u32,
+    pub ok: u32,
+    pub fast: u32,
+    pub slow: u32,
+    pub pb: Option<u32>,
+    pub pb_delta: Option<i64>,
+    pub time: String,
+    pub skip_reason: Option<String>,
+}
+
+fn to_text<T: Serialize>(value: &T) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|value| value.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+impl Stage {
+    pub fn new(event: &ScoreEvent, score: &ImportScore) -> Self {
+        let pb = history::personal_best(&event.card, &event.import, score);
+
+        Stage {
+            card: event.card.clone(),
+            konami_id: cards::to_konami_id(&event.card).unwrap_or_default(),
+            ref_id: event.ref_id.clone(),
+            title: music::title(&score.identifier)
+                .unwrap_or(&score.identifier)
+                .to_string(),
+            mcode: score.identifier.clone(),
+            playtype: to_text(&event.import.meta.play_type),
+            difficulty: to_text(&score.difficulty),
+            score: score.score,
+            ex_score: score.hit_meta.ex_score,
+            lamp: to_text(&score.lamp),
+            flare: match score.optional.flare {
+                Flare::None => None,
+                ref flare => Some(to_text(flare)),
+            },
+            max_combo: score.hit_meta.max_combo,
+            marvelous: score.judgements.marvelous,
+            perfect: score.judgements.perfect,
+            great: score.judgements.great,
+            good: score.judgements.good,
+            miss: score.judgements.miss,
+            ok: score.judgements.ok,
+            fast: score.hit_meta.fast,
+            slow: score.hit_meta.slow,
+            pb,
+            pb_delta: pb.map(|pb| score.score as i64 - pb as i64),
+            time: chrono::DateTime::<chrono::Utc>::from_timestamp_millis(score.time_achieved as i64)
+                .map(|time| time.to_rfc3339())
+                .unwrap_or_default(),
+            skip_reason: event.skip_reason.clone(),
+        }
+    }
+
+    /// Copy of this stage with the card and refid of the player of an event
+    pub fn with_player(&self, event: &ScoreEvent) -> Self {
+        Stage {
+            card: event.card.clone(),
+            konami_id: cards::to_konami_id(&event.card).unwrap_or_default(),
+            ref_id: event.ref_id.clone(),
+            ..self.clone()
+        }
+    }
+
+    /// Copy of this stage without the card and refid of the player, for what is shown on stream
+    pub fn without_player(&self) -> Self {
+        Stage {
+            card: String::new(),
+            konami_id: String::new(),
+            ref_id: String::new(),
+            ..self.clone()
+        }
+    }
+
+    /// Whether this stage beats the previous personal best, or is the first play of the chart
+    pub fn is_personal_best(&self) -> bool {
+        self.pb_delta.map(|delta| delta > 0).unwrap_or(true)
+    }
+}
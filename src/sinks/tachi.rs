@@ -0,0 +1,79 @@
+use anyhow::Result;
+use crate::configuration::FailurePolicy;
+use crate::helpers::{self, RequestError};
+use crate::history::Status;
+use crate::sinks::{Outcome, Report, ScoreEvent, ScoreSink};
+use crate::tachi::Instance;
+use crate::{cards, circuit, queue};
+use log::{debug, info};
+use std::sync::atomic::Ordering;
+
+/// Submits scores to a Tachi instance, queueing them while it cannot be reached
+pub struct TachiSink {
+    instance: &'static Instance,
+}
+
+impl TachiSink {
+    pub fn new(instance: &'static Instance) -> Self {
+        TachiSink { instance }
+    }
+}
+
+impl ScoreSink for TachiSink {
+    fn kind(&self) -> &'static str {
+        "tachi"
+    }
+
+    fn name(&self) -> String {
+        format!("tachi {}", self.instance)
+    }
+
+    fn is_main(&self) -> bool {
+        self.instance.main
+    }
+
+    fn submit(&self, event: &ScoreEvent, _reports: &[Report]) -> Result<Outcome> {
+        if cfg!(debug_assertions) {
+            debug!("Tachi API request data: {:#?}", event.import);
+            return Ok(Outcome::new(Status::Unsubmitted));
+        }
+
+        let instance = self.instance;
+        if !instance.online.load(Ordering::Relaxed) || !circuit::allow_request(instance) {
+            return Err(RequestError::Transient {
+                message: format!("Tachi API of {} is unreachable", instance),
+                retry_after: None,
+            }
+            .into());
+        }
+
+        // A single attempt, failed scores are queued and retried in the background
+        let response = queue::submit(instance, &event.import, 0).inspect_err(|err| {
+            // Retrying cannot fix a refused API key, the instance is not reached until it changes
+            if helpers::is_unauthorized(err) {
+                instance.disable();
+            }
+        })?;
+        if !queue::is_empty(instance) {
+            queue::flush_in_background(instance);
+        }
+        info!(
+            "Successfully imported score(s) for card {} to {}",
+            cards::Card(&event.card),
+            instance
+        );
+
+        Ok(Outcome {
+            status: Status::Submitted,
+            response,
+        })
+    }
+
+    fn queue(&self, event: &ScoreEvent) -> Result<()> {
+        queue::push(self.instance, &event.card, &event.import)
+    }
+
+    fn default_policy(&self) -> FailurePolicy {
+        FailurePolicy::Queue
+    }
+}
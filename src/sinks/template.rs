@@ -0,0 +1,100 @@
+use crate::sinks::stage::Stage;
+
+/// Fields available in templates, as `{{name}}`, the same as the fields of `Stage`
+pub const FIELDS: &[&str] = &[
+    "card",
+    "konami_id",
+    "ref_id",
+    "title",
+    "mcode",
+    "playtype",
+    "difficulty",
+    "score",
+    "ex_score",
+    "lamp",
+    "flare",
+    "max_combo",
+    "marvelous",
+    "perfect",
+    "great",
+    "good",
+    "miss",
+    "ok",
+    "fast",
+    "slow",
+    "pb",
+    "pb_delta",
+    "time",
+    "skip_reason",
+];
+
+/// Values of every template field of a stage
+pub fn fields(stage: &Stage) -> Vec<(&'static str, String)> {
+    let values = serde_json::to_value(stage).unwrap_or_default();
+
+    FIELDS
+        .iter()
+        .map(|field| {
+            let value = match (*field, &values[field]) {
+                ("pb_delta", serde_json::Value::Number(delta)) => {
+                    format!("{:+}", delta.as_i64().unwrap_or_default())
+                }
+                (_, serde_json::Value::String(value)) => value.clone(),
+                (_, serde_json::Value::Null) => String::new(),
+                (_, value) => value.to_string(),
+            };
+            (*field, value)
+        })
+        .collect()
+}
+
+/// Calls `f` with the name of every placeholder of the template and where it is
+fn placeholders(template: &str, mut f: impl FnMut(std::ops::Range<usize>, &str)) {
+    let mut offset = 0;
+    while let Some(start) = template[offset..].find("{{").map(|start| offset + start) {
+        let end = match template[start..].find("}}") {
+            Some(end) => start + end + 2,
+            None => break,
+        };
+        f(start..end, template[start + 2..end - 2].trim());
+        offset = end;
+    }
+}
+
+/// Returns the placeholders of the template which are neither stage fields nor extra fields
+pub fn unknown_fields(template: &str, extra: &[&str]) -> Vec<String> {
+    let mut unknown = Vec::new();
+    placeholders(template, |_, name| {
+        if !FIELDS.contains(&name) && !extra.contains(&name) {
+            unknown.push(name.to_string());
+        }
+    });
+
+    unknown
+}
+
+/// Replaces every known placeholder of the template with its escaped value
+pub fn render(
+    template: &str,
+    fields: &[(&str, String)],
+    escape: impl Fn(&str) -> String,
+) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut last = 0;
+    placeholders(template, |range, name| {
+        if let Some((_, value)) = fields.iter().find(|(field, _)| *field == name) {
+            rendered.push_str(&template[last..range.start]);
+            rendered.push_str(&escape(value));
+            last = range.end;
+        }
+    });
+    rendered.push_str(&template[last..]);
+
+    rendered
+}
+
+/// Escapes a value to be placed inside a JSON string
+pub fn escape_json(value: &str) -> String {
+    let quoted = serde_json::Value::String(value.to_string()).to_string();
+    quoted[1..quoted.len() - 1].to_string()
+}
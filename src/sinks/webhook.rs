@@ -0,0 +1,197 @@
+use anyhow::Result;
+use crate::configuration::FailurePolicy;
+use crate::helpers::{self, RequestError};
+use crate::history::Status;
+use crate::queue::Queue;
+use crate::sinks::stage::Stage;
+use crate::sinks::{template, Outcome, Report, ScoreEvent, ScoreSink};
+use crate::CONFIGURATION;
+use log::{error, info, warn};
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Posts every score as JSON to a custom URL, queueing payloads while it cannot be reached
+pub struct WebhookSink {
+    inner: Arc<Webhook>,
+}
+
+struct Webhook {
+    url: String,
+    template: Option<String>,
+    /// Host of the URL, the URL itself often contains a secret token and is not logged
+    name: String,
+    queue: Queue,
+    flushing: AtomicBool,
+}
+
+impl WebhookSink {
+    pub fn new(url: &str, template: Option<String>) -> Self {
+        let name = url::Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_else(|| "invalid URL".to_string());
+        let hash = Sha256::digest(url.as_bytes())
+            .iter()
+            .take(4)
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+        let slug = helpers::file_slug(&name);
+
+        if let Some(template) = &template {
+            let unknown = template::unknown_fields(template, &[]);
+            if !unknown.is_empty() {
+                warn!(
+                    "Template of webhook '{}' has unknown field(s) '{}', they will be sent as is",
+                    name,
+                    unknown.join("', '")
+                );
+            }
+        }
+
+        WebhookSink {
+            inner: Arc::new(Webhook {
+                url: url.to_string(),
+                template,
+                queue: Queue::new(&format!("takure.webhook.{}-{}.queue.jsonl", slug, hash)),
+                name,
+                flushing: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    /// Sends queued payloads on a separate thread, retrying with backoff without holding the
+    /// next sinks
+    fn spawn_flush(&self) {
+        if self.inner.queue.is_empty() || self.inner.flushing.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let inner = self.inner.clone();
+        std::thread::spawn(move || {
+            if let Err(err) = inner.flush() {
+                error!("{:#}", err);
+            }
+            inner.flushing.store(false, Ordering::SeqCst);
+        });
+    }
+}
+
+impl Webhook {
+    /// Builds the payloads of an event, one per score
+    ///
+    /// Webhooks usually post to third-party services, the card and refid of the player are only
+    /// sent when the template asks for them.
+    fn payloads(&self, event: &ScoreEvent) -> Result<Vec<serde_json::Value>> {
+        event
+            .import
+            .scores
+            .iter()
+            .map(|score| {
+                let stage = Stage::new(event, score);
+                let template = match &self.template {
+                    Some(template) => template,
+                    None => return Ok(serde_json::to_value(stage.without_player())?),
+                };
+
+                let fields = template::fields(&stage);
+                let rendered = template::render(template, &fields, template::escape_json);
+                serde_json::from_str(&rendered).map_err(|err| {
+                    RequestError::Rejected {
+                        message: format!(
+                            "Template of webhook '{}' is not valid JSON once rendered: {:#}",
+                            self.name, err
+                        ),
+                    }
+                    .into()
+                })
+            })
+            .collect()
+    }
+
+    fn send(&self, payload: &serde_json::Value, retries: u32) -> Result<()> {
+        let agent = helpers::request_agent();
+        helpers::with_retries(
+            retries,
+            || {
+                agent
+                    .post(&self.url)
+                    .send_json(payload)
+                    .map_err(|err| RequestError::from_webhook(&format!("'{}'", self.name), err))
+            },
+            || true,
+        )?;
+
+        Ok(())
+    }
+
+    /// Sends every queued payload, removing each of them from the queue once it was sent or
+    /// rejected
+    fn flush(&self) -> Result<()> {
+        let _flushing = match self.queue.try_lock_flush() {
+            Some(guard) => guard,
+            None => return Ok(()),
+        };
+
+        let queued = self.queue.peek::<serde_json::Value>()?;
+        if queued.is_empty() {
+            return Ok(());
+        }
+
+        info!("Sending {} queued payload(s) to webhook '{}'", queued.len(), self.name);
+
+        let retries = CONFIGURATION.get().general.retries;
+        let total = queued.len();
+        for (index, entry) in queued.iter().enumerate() {
+            if let Err(err) = self.send(&entry.value, retries) {
+                error!("Could not send queued payload: {:#}", err);
+                if !helpers::is_rejected(&err) {
+                    warn!("{} payload(s) are still queued for webhook '{}'", total - index, self.name);
+                    return Ok(());
+                }
+                error!("Dropping rejected queued payload: {}", entry.value);
+            }
+
+            self.queue.remove(entry)?;
+        }
+
+        info!("All queued payloads were sent to webhook '{}'", self.name);
+
+        Ok(())
+    }
+}
+
+impl ScoreSink for WebhookSink {
+    fn kind(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn name(&self) -> String {
+        format!("webhook '{}'", self.inner.name)
+    }
+
+    fn submit(&self, event: &ScoreEvent, _reports: &[Report]) -> Result<Outcome> {
+        // A single attempt, failed payloads are queued and retried in the background
+        for payload in self.inner.payloads(event)? {
+            self.inner.send(&payload, 0)?;
+        }
+
+        self.spawn_flush();
+
+        Ok(Outcome::new(Status::Submitted))
+    }
+
+    fn queue(&self, event: &ScoreEvent) -> Result<()> {
+        for payload in self.inner.payloads(event)? {
+            self.inner.queue.push(payload)?;
+        }
+
+        self.spawn_flush();
+
+        Ok(())
+    }
+
+    fn default_policy(&self) -> FailurePolicy {
+        FailurePolicy::Queue
+    }
+}
@@ -0,0 +1,213 @@
+use anyhow::Result;
+use crate::configuration::{TachiConfiguration, TachiInstanceConfiguration};
+use crate::{circuit, fingerprint, helpers, paths, queue, CONFIGURATION};
+use lazy_static::lazy_static;
+use log::{error, info};
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::RwLock;
+use url::Url;
+
+/// A Tachi instance scores are submitted to, with its own status, circuit breaker and queue
+pub struct Instance {
+    pub name: String,
+    /// The instance configured in `[tachi]`, whose outcome is recorded in the score history
+    pub main: bool,
+    api_key: RwLock<String>,
+    base_url: Url,
+    pub status_url: String,
+    pub import_url: String,
+    pub user: AtomicU64,
+    pub username: RwLock<Option<String>>,
+    pub online: AtomicBool,
+    pub breaker: circuit::Breaker,
+    pub queue: queue::Queue,
+    pub confirmed: fingerprint::Record,
+}
+
+impl Instance {
+    fn new(configuration: &TachiInstanceConfiguration, main: bool) -> Result<Self> {
+        let base_url = Url::parse(&configuration.base_url)
+            .map_err(|err| anyhow::anyhow!("Could not parse Tachi URL '{}': {:#}", configuration.base_url, err))?;
+        let name = configuration
+            .name
+            .clone()
+            .or_else(|| base_url.host_str().map(str::to_string))
+            .unwrap_or_else(|| configuration.base_url.clone());
+
+        let (queue_path, confirmed_path) = if main {
+            (queue::QUEUE_PATH.to_string(), fingerprint::CONFIRMED_PATH.to_string())
+        } else {
+            let slug = helpers::file_slug(&name);
+            (format!("takure.{}.queue.jsonl", slug), format!("takure.{}.submitted", slug))
+        };
+
+        let join = |path: &str| {
+            base_url
+                .join(path)
+                .map(|url| url.to_string())
+                .map_err(|err| anyhow::anyhow!("Could not parse Tachi URL of '{}': {:#}", name, err))
+        };
+        let status_url = join("/api/v1/status")?;
+        let import_url = join("/ir/direct-manual/import")?;
+
+        Ok(Instance {
+            main,
+            api_key: RwLock::new(configuration.api_key.clone()),
+            base_url,
+            status_url,
+            import_url,
+            user: AtomicU64::new(0),
+            username: RwLock::new(None),
+            online: AtomicBool::new(false),
+            breaker: circuit::Breaker::new(),
+            queue: queue::Queue::new(&queue_path),
+            confirmed: fingerprint::Record::new(paths::resolve(&confirmed_path)),
+            name,
+        })
+    }
+
+    pub fn api_key(&self) -> String {
+        self.api_key
+            .read()
+            .map(|api_key| api_key.clone())
+            .unwrap_or_else(|err| err.into_inner().clone())
+    }
+
+    /// Name of the Tachi user the API key belongs to, once its status was checked
+    pub fn username(&self) -> Option<String> {
+        self.username
+            .read()
+            .map(|username| username.clone())
+            .unwrap_or_else(|err| err.into_inner().clone())
+    }
+
+    /// Stops reaching the instance after its API key was refused, scores are queued until the key
+    /// is changed in takure.toml, which checks its status again
+    pub fn disable(&self) {
+        self.online.store(false, Ordering::Relaxed);
+        error!(
+            "Scores will be queued for {} until its API key is fixed in takure.toml, its status will not be checked again until then",
+            self
+        );
+    }
+
+    pub fn url(&self, path: &str) -> Result<String> {
+        let url = self
+            .base_url
+            .join(path)
+            .map_err(|err| anyhow::anyhow!("Could not parse Tachi URL: {:#}", err))?;
+
+        Ok(url.to_string())
+    }
+}
+
+impl fmt::Display for Instance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "'{}'", self.name)
+    }
+}
+
+/// Builds the instances of a configuration, reusing previous instances with the same name and URL
+/// so that their status, circuit and queue are kept
+///
+/// Returns the instances, and the ones whose status must be checked because they are new or their
+/// API key changed.
+fn build_instances(
+    tachi: &TachiConfiguration,
+    previous: &[&'static Instance],
+) -> (Vec<&'static Instance>, Vec<&'static Instance>) {
+    let main = TachiInstanceConfiguration {
+        name: tachi.name.clone(),
+        base_url: tachi.base_url.clone(),
+        api_key: tachi.api_key.clone(),
+    };
+
+    let mut names = HashSet::new();
+    let mut unchecked = Vec::new();
+    let instances = std::iter::once((&main, true))
+        .chain(tachi.instances.iter().map(|instance| (instance, false)))
+        .filter_map(|(configuration, main)| match Instance::new(configuration, main) {
+            Ok(instance) if !names.insert(instance.name.clone()) => {
+                error!(
+                    "Tachi instance name '{}' is used more than once, set a different 'name' for each instance",
+                    instance.name
+                );
+                None
+            }
+            Ok(instance) => {
+                let reused = previous.iter().find(|previous| {
+                    previous.name == instance.name
+                        && previous.base_url == instance.base_url
+                        && previous.main == instance.main
+                });
+                match reused {
+                    Some(&reused) => {
+                        if reused.api_key() != configuration.api_key {
+                            info!("API key of {} changed", reused);
+                            if let Ok(mut api_key) = reused.api_key.write() {
+                                *api_key = configuration.api_key.clone();
+                            }
+                            reused.online.store(false, Ordering::Relaxed);
+                            unchecked.push(reused);
+                        }
+                        Some(reused)
+                    }
+                    None => {
+                        // Instances are shared with background threads for as long as the game runs
+                        let instance: &'static Instance = Box::leak(Box::new(instance));
+                        unchecked.push(instance);
+                        Some(instance)
+                    }
+                }
+            }
+            Err(err) => {
+                error!("{:#}, scores will not be submitted to it", err);
+                None
+            }
+        })
+        .collect();
+
+    (instances, unchecked)
+}
+
+lazy_static! {
+    static ref INSTANCES: RwLock<Vec<&'static Instance>> =
+        RwLock::new(build_instances(&CONFIGURATION.get().tachi, &[]).0);
+}
+
+/// Instances scores are currently submitted to
+pub fn instances() -> Vec<&'static Instance> {
+    INSTANCES
+        .read()
+        .map(|instances| instances.clone())
+        .unwrap_or_else(|err| err.into_inner().clone())
+}
+
+/// Replaces the instances after a configuration change, returns the ones whose status must be
+/// checked
+pub fn reload(tachi: &TachiConfiguration) -> Vec<&'static Instance> {
+    let previous = instances();
+    let (instances, unchecked) = build_instances(tachi, &previous);
+    for instance in &previous {
+        if !instances.iter().any(|kept| std::ptr::eq(*kept, *instance)) {
+            info!("{} was removed, scores will not be submitted to it anymore", instance);
+        }
+    }
+
+    match INSTANCES.write() {
+        Ok(mut guard) => *guard = instances,
+        Err(err) => *err.into_inner() = instances,
+    }
+
+    unchecked
+}
+
+pub fn main() -> Option<&'static Instance> {
+    instances().into_iter().find(|instance| instance.main)
+}
+
+pub fn find(name: &str) -> Option<&'static Instance> {
+    instances().into_iter().find(|instance| instance.name == name)
+}
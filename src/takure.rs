@@ -1,17 +1,25 @@
 use anyhow::Result;
-use crate::{helpers, CONFIGURATION, TACHI_STATUS_URL};
+use crate::configuration::{self, Configuration};
+use crate::{helpers, paths, queue, sinks, tachi, CONFIGURATION};
+#[cfg(windows)]
+use crate::{cards, scripts};
+#[cfg(windows)]
 use crate::handlers::scores::process_scores;
+#[cfg(windows)]
 use crate::sys::{property_clear_error, property_mem_write, property_node_name, property_node_refer, property_query_size, property_search, property_set_flag, NodeType};
+use crate::helpers::RequestError;
+use crate::tachi::Instance;
+#[cfg(windows)]
 use crate::types::game::{Property2, Property3};
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::Ordering;
 use std::sync::RwLock;
 use log::{debug, error, info, warn};
 
-pub static USER: AtomicU64 = AtomicU64::new(0);
 pub static CURRENT_CARD_ID: RwLock<Option<String>> = RwLock::new(None);
 
+#[cfg(windows)]
 pub fn hook_init(ea3_node: *const ()) -> Result<()> {
-    if !CONFIGURATION.general.enable {
+    if !CONFIGURATION.get().general.enable {
         return Ok(());
     }
 
@@ -42,19 +50,19 @@ pub fn hook_init(ea3_node: *const ()) -> Result<()> {
         warn!("Could not read game version, hook might not work properly");
     }
 
-    // Trying to reach Tachi API
+    // Trying to reach Tachi API of every instance in the background, they queue scores until then
     if cfg!(debug_assertions) {
         info!("Debug mode is enabled, not reaching Tachi API");
     } else {
-        let response: serde_json::Value =
-            helpers::request_tachi("GET", TACHI_STATUS_URL.as_str(), None::<()>)?;
-        let user = response["body"]["whoami"]
-            .as_u64()
-            .ok_or(anyhow::anyhow!("Couldn't parse user from Tachi response"))?;
-        USER.store(user, Ordering::Relaxed);
-        info!("Tachi API successfully reached, user {}", user);
+        for instance in tachi::instances() {
+            std::thread::spawn(move || start_instance(instance));
+        }
     }
 
+    scripts::init();
+    sinks::init();
+    spawn_configuration_watch();
+
     // Initializing function detours
     crochet::enable!(property_destroy_hook)
         .map_err(|err| anyhow::anyhow!("Could not enable function detour: {:#}", err))?;
@@ -64,8 +72,184 @@ pub fn hook_init(ea3_node: *const ()) -> Result<()> {
     Ok(())
 }
 
+/// Checks the status of an instance, then submits its queued scores, or keeps checking it in the
+/// background until it can be reached
+fn start_instance(instance: &'static Instance) {
+    if let Err(err) = check_tachi_status(instance) {
+        error!("{:#}", err);
+        if helpers::is_unauthorized(&err) {
+            instance.disable();
+            return;
+        }
+        warn!(
+            "Starting {} in degraded mode, scores will be queued until its Tachi API can be reached",
+            instance
+        );
+        spawn_status_check(instance);
+    } else {
+        queue::flush_in_background(instance);
+    }
+}
+
+/// Reloads takure.toml when it changes
+fn spawn_configuration_watch() {
+    let modified = || {
+        std::fs::metadata(paths::resolve(configuration::PATH))
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+
+    let mut last = modified();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(std::time::Duration::from_secs(2));
+        let current = modified();
+        if current != last {
+            last = current;
+            reload_configuration();
+        }
+    });
+}
+
+fn changed<T: serde::Serialize>(previous: &T, current: &T) -> bool {
+    serde_json::to_value(previous).ok() != serde_json::to_value(current).ok()
+}
+
+/// Replaces the configuration, the Tachi instances and the sinks, keeping the current
+/// configuration if the new one is not valid
+fn reload_configuration() {
+    let configuration = match Configuration::load() {
+        Ok(configuration) => configuration,
+        Err(err) => {
+            error!("{:#}, keeping the current configuration", err);
+            return;
+        }
+    };
+
+    let previous = CONFIGURATION.get();
+    let restart = [
+        ("general.enable", previous.general.enable != configuration.general.enable),
+        ("overlay", changed(&previous.overlay, &configuration.overlay)),
+        ("now_playing", changed(&previous.now_playing, &configuration.now_playing)),
+        ("mqtt", changed(&previous.mqtt, &configuration.mqtt)),
+        ("scripts", changed(&previous.scripts, &configuration.scripts)),
+    ]
+    .into_iter()
+    .filter(|(_, changed)| *changed)
+    .map(|(section, _)| section)
+    .collect::<Vec<_>>();
+    if !restart.is_empty() {
+        warn!(
+            "Changes to '{}' will only be applied after restarting the game",
+            restart.join("', '")
+        );
+    }
+
+    CONFIGURATION.replace(configuration);
+    let configuration = CONFIGURATION.get();
+    for instance in tachi::reload(&configuration.tachi) {
+        if !cfg!(debug_assertions) {
+            std::thread::spawn(move || start_instance(instance));
+        }
+    }
+    sinks::reload(&configuration);
+
+    info!("Configuration reloaded");
+}
+
+const REQUIRED_PERMISSIONS: &[&str] = &["submit_score"];
+
+fn check_tachi_status(instance: &Instance) -> Result<()> {
+    let response: serde_json::Value =
+        helpers::request_tachi(instance, "GET", &instance.status_url, None::<()>)?;
+    let user = response["body"]["whoami"]
+        .as_u64()
+        .ok_or_else(|| RequestError::Unauthorized {
+            message: format!(
+                "Tachi API key of {} is not valid, it might have been revoked, please check your configuration",
+                instance
+            ),
+        })?;
+    let username = get_tachi_username(instance, user);
+
+    let permissions = response["body"]["permissions"]
+        .as_array()
+        .map(|permissions| {
+            permissions
+                .iter()
+                .filter_map(|permission| permission.as_str())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let missing = REQUIRED_PERMISSIONS
+        .iter()
+        .filter(|permission| !permissions.contains(permission))
+        .copied()
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        return Err(RequestError::Unauthorized {
+            message: format!(
+                "Tachi API key of user '{}' on {} is missing the '{}' permission(s), please create a new key with these permissions",
+                username,
+                instance,
+                missing.join("', '")
+            ),
+        }
+        .into());
+    }
+
+    instance.user.store(user, Ordering::Relaxed);
+    if let Ok(mut guard) = instance.username.write() {
+        *guard = Some(username.clone());
+    }
+    instance.online.store(true, Ordering::Relaxed);
+    info!(
+        "Tachi API of {} successfully reached, user '{}' ({})",
+        instance, username, user
+    );
+
+    Ok(())
+}
+
+fn get_tachi_username(instance: &Instance, user: u64) -> String {
+    instance
+        .url(&format!("/api/v1/users/{}", user))
+        .and_then(|url| helpers::request_tachi::<(), serde_json::Value>(instance, "GET", url, None))
+        .ok()
+        .and_then(|response| response["body"]["username"].as_str().map(str::to_string))
+        .unwrap_or_else(|| user.to_string())
+}
+
+/// Periodically checks Tachi status until it succeeds, then submits queued scores
+fn spawn_status_check(instance: &'static Instance) {
+    let interval = std::time::Duration::from_secs(CONFIGURATION.get().general.retry_interval.max(1));
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+        if !tachi::instances().iter().any(|current| std::ptr::eq(*current, instance)) {
+            debug!("{} was removed, not checking its status anymore", instance);
+            break;
+        }
+
+        match check_tachi_status(instance) {
+            Ok(()) => {
+                info!("{} is leaving degraded mode, scores will be submitted live", instance);
+                if let Err(err) = queue::flush(instance) {
+                    error!("{:#}", err);
+                }
+                break;
+            }
+            Err(err) if helpers::is_unauthorized(&err) => {
+                error!("{:#}", err);
+                instance.disable();
+                break;
+            }
+            Err(err) => debug!("Tachi API of {} is still unreachable: {:#}", instance, err),
+        }
+    });
+}
+
+#[cfg(windows)]
 pub fn hook_release() -> Result<()> {
-    if !CONFIGURATION.general.enable {
+    if !CONFIGURATION.get().general.enable {
         return Ok(());
     }
 
@@ -77,6 +261,7 @@ pub fn hook_release() -> Result<()> {
     Ok(())
 }
 
+#[cfg(windows)]
 #[cfg_attr(target_arch = "x86", crochet::hook("libavs-win32.dll", "XCd229cc00013c"))]
 #[cfg_attr(target_arch = "x86_64", crochet::hook("libavs-win64.dll", "XCnbrep7000091"))]
 pub unsafe extern "C" fn property_destroy_hook(property: *mut ()) -> i32 {
@@ -173,7 +358,8 @@ pub unsafe extern "C" fn property_destroy_hook(property: *mut ()) -> i32 {
         };
 
         if let Ok(mut guard) = CURRENT_CARD_ID.write() {
-            debug!("Set current card id to {}", cardid);
+            debug!("Set current card id to {}", cards::Card(&cardid));
+            sinks::card_in(&cardid);
             *guard = Some(cardid);
         } else {
             warn!("Could not acquire write lock on current card id");
@@ -0,0 +1,333 @@
+use crate::cards;
+use crate::configuration::{Configuration, SinkKind, PATH};
+use std::fmt;
+use std::ops::Range;
+use toml_edit::{ImDocument, Item, Table, TableLike, Value};
+use url::Url;
+
+/// API key written in the bundled takure.toml, to be replaced by the user
+const PLACEHOLDER_API_KEYS: &[&str] = &["your-key-here", "your-other-key-here"];
+
+/// A problem found in takure.toml, with the position of the key or value it is about
+pub struct Diagnostic {
+    pub error: bool,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "{}: {}", PATH, self.message)
+        } else {
+            write!(f, "{}:{}:{}: {}", PATH, self.line, self.column, self.message)
+        }
+    }
+}
+
+struct Checker<'a> {
+    text: &'a str,
+    document: Option<ImDocument<&'a str>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Checker<'a> {
+    fn push(&mut self, error: bool, span: Option<Range<usize>>, message: String) {
+        let (line, column) = span
+            .map(|span| position(self.text, span.start))
+            .unwrap_or((0, 0));
+        self.diagnostics.push(Diagnostic {
+            error,
+            line,
+            column,
+            message,
+        });
+    }
+
+    fn push_at(&mut self, error: bool, path: &str, message: String) {
+        let (_, span) = self.find(path);
+        self.push(error, span, message);
+    }
+
+    /// Finds a dotted path such as `tachi.instances.0.base_url`, returning the span of its last key
+    /// if it exists, and the span of its value or of the closest parent which exists
+    fn find(&self, path: &str) -> (Option<Range<usize>>, Option<Range<usize>>) {
+        let mut node = match &self.document {
+            Some(document) => Node::Item(document.as_item()),
+            None => return (None, None),
+        };
+
+        let (mut key, mut span) = (None, None);
+        for segment in path.split('.') {
+            match node.child(segment) {
+                Some((child_key, child)) => {
+                    key = child_key;
+                    span = child.span().or(span);
+                    node = child;
+                }
+                None => return (None, span),
+            }
+        }
+
+        (key, span)
+    }
+
+    /// Returns the keys of the table at a dotted path, if there is one
+    fn keys(&self, path: &str) -> Vec<String> {
+        let mut node = match &self.document {
+            Some(document) => Node::Item(document.as_item()),
+            None => return Vec::new(),
+        };
+        for segment in path.split('.') {
+            match node.child(segment) {
+                Some((_, child)) => node = child,
+                None => return Vec::new(),
+            }
+        }
+
+        node.table()
+            .map(|table| table.iter().map(|(key, _)| key.to_string()).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// A part of the document, array elements not being items
+enum Node<'a> {
+    Item(&'a Item),
+    Table(&'a Table),
+    Value(&'a Value),
+}
+
+impl<'a> Node<'a> {
+    fn span(&self) -> Option<Range<usize>> {
+        match self {
+            Node::Item(item) => item.span(),
+            Node::Table(table) => table.span(),
+            Node::Value(value) => value.span(),
+        }
+    }
+
+    fn child(&self, segment: &str) -> Option<(Option<Range<usize>>, Node<'a>)> {
+        if let Ok(index) = segment.parse::<usize>() {
+            let array = match self {
+                Node::Item(item) => {
+                    if let Some(tables) = item.as_array_of_tables() {
+                        return tables.get(index).map(|table| (None, Node::Table(table)));
+                    }
+                    item.as_array()
+                }
+                Node::Value(value) => value.as_array(),
+                Node::Table(_) => None,
+            };
+            return array
+                .and_then(|array| array.get(index))
+                .map(|value| (None, Node::Value(value)));
+        }
+
+        self.table()
+            .and_then(|table| table.get_key_value(segment))
+            .map(|(key, item)| (key.span(), Node::Item(item)))
+    }
+
+    fn table(&self) -> Option<&'a dyn TableLike> {
+        match self {
+            Node::Item(item) => item.as_table_like(),
+            Node::Table(table) => Some(*table),
+            Node::Value(value) => value.as_inline_table().map(|table| table as &dyn TableLike),
+        }
+    }
+}
+
+/// Returns the 1-based line and column of a byte offset
+fn position(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .map(|line| line.chars().count())
+        .unwrap_or(0)
+        + 1;
+
+    (line, column)
+}
+
+fn check_url(checker: &mut Checker, path: &str, url: &str, error: bool) {
+    match Url::parse(url) {
+        Ok(parsed) if parsed.scheme() == "http" || parsed.scheme() == "https" => {}
+        Ok(_) => checker.push_at(error, path, format!("'{}' is not an http or https URL", url)),
+        Err(err) => checker.push_at(error, path, format!("'{}' is not a valid URL: {}", url, err)),
+    }
+}
+
+fn check_api_key(checker: &mut Checker, path: &str, api_key: &str, error: bool) {
+    if api_key.trim().is_empty() || PLACEHOLDER_API_KEYS.contains(&api_key.trim()) {
+        checker.push_at(
+            error,
+            path,
+            "API key is not set, create one on your Tachi instance and paste it here"
+                .to_string(),
+        );
+    }
+}
+
+fn check_cards(checker: &mut Checker, list: &str, entries: &[String]) {
+    for (index, entry) in entries.iter().enumerate() {
+        let is_pattern = entry.starts_with("refid:") || entry.contains('*') || entry.contains('?');
+        if !is_pattern && cards::normalize(entry).is_none() {
+            checker.push_at(
+                false,
+                &format!("cards.{}.{}", list, index),
+                format!(
+                    "'{}' is not a valid card number or card UID, it will only match this exact text",
+                    entry
+                ),
+            );
+        }
+    }
+}
+
+/// Keys of a `[[sinks]]` entry of this type
+///
+/// Entries are flattened when deserialized, which keeps serde_ignored from reporting their unknown
+/// keys, so they are checked here.
+fn sink_keys(kind: &SinkKind) -> &'static [&'static str] {
+    match kind {
+        SinkKind::Tachi { .. } => &["instance"],
+        SinkKind::Jsonl { .. } => &["path"],
+        SinkKind::Database => &[],
+        SinkKind::Webhook { .. } => &["url", "template"],
+        SinkKind::Command { .. } => &["command", "timeout"],
+    }
+}
+
+fn check_sink_keys(checker: &mut Checker, index: usize, kind: &SinkKind) {
+    let path = format!("sinks.{}", index);
+    for key in checker.keys(&path) {
+        let known = ["type", "on_failure", "skip"].iter().chain(sink_keys(kind));
+        if known.into_iter().any(|known| *known == key) {
+            continue;
+        }
+
+        let key = format!("{}.{}", path, key);
+        checker.push_at(
+            false,
+            &key,
+            format!("Unknown key '{}', it is ignored (typo or outdated option?)", key),
+        );
+    }
+}
+
+fn check(checker: &mut Checker, configuration: &Configuration) {
+    let tachi = &configuration.tachi;
+    check_url(checker, "tachi.base_url", &tachi.base_url, true);
+    check_api_key(checker, "tachi.api_key", &tachi.api_key, true);
+    for (index, instance) in tachi.instances.iter().enumerate() {
+        let path = format!("tachi.instances.{}", index);
+        check_url(checker, &format!("{}.base_url", path), &instance.base_url, false);
+        check_api_key(checker, &format!("{}.api_key", path), &instance.api_key, false);
+    }
+
+    check_cards(checker, "whitelist", &configuration.cards.whitelist);
+    check_cards(checker, "blacklist", &configuration.cards.blacklist);
+
+    for (index, sink) in configuration.sinks.iter().enumerate() {
+        check_sink_keys(checker, index, &sink.kind);
+        if let SinkKind::Webhook { url, .. } = &sink.kind {
+            check_url(checker, &format!("sinks.{}.url", index), url, false);
+        }
+    }
+
+    if configuration.overlay.allowed_origin.contains(|c: char| c.is_control()) {
+        checker.push_at(
+            true,
+            "overlay.allowed_origin",
+            "Allowed origin cannot contain line breaks".to_string(),
+        );
+    }
+}
+
+/// Parses takure.toml, reporting syntax errors, invalid values, unknown keys and values that
+/// cannot work
+///
+/// Returns the configuration if it could be parsed, which should only be used if there is no error
+/// among the diagnostics.
+pub fn parse(text: &str) -> (Option<Configuration>, Vec<Diagnostic>) {
+    let mut checker = Checker {
+        text,
+        document: None,
+        diagnostics: Vec::new(),
+    };
+
+    match ImDocument::parse(text) {
+        Ok(document) => checker.document = Some(document),
+        Err(err) => {
+            checker.push(true, err.span(), err.message().trim().to_string());
+            return (None, checker.diagnostics);
+        }
+    }
+
+    let mut unknown = Vec::new();
+    let result = toml_edit::de::Deserializer::parse(text).and_then(|deserializer| {
+        serde_ignored::deserialize(deserializer, |path| unknown.push(path.to_string()))
+    });
+    let configuration: Configuration = match result {
+        Ok(configuration) => configuration,
+        Err(err) => {
+            checker.push(true, err.span(), err.message().trim().to_string());
+            return (None, checker.diagnostics);
+        }
+    };
+
+    for path in unknown {
+        let (key, parent) = checker.find(&path);
+        checker.push(
+            false,
+            key.or(parent),
+            format!("Unknown key '{}', it is ignored (typo or outdated option?)", path),
+        );
+    }
+    check(&mut checker, &configuration);
+
+    (Some(configuration), checker.diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn warnings(sinks: &str) -> Vec<String> {
+        let text = format!("{}\n{}", include_str!("../takure.toml"), sinks);
+        let (configuration, diagnostics) = parse(&text);
+        assert!(configuration.is_some());
+        diagnostics
+            .iter()
+            .filter(|diagnostic| !diagnostic.error)
+            .map(|diagnostic| diagnostic.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn reports_misspelled_sink_keys() {
+        let warnings = warnings(
+            "[[sinks]]\ntype = 'webhook'\nurl = 'https://example.com/hook'\ntempalte = '{}'\n",
+        );
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("Unknown key 'sinks.0.tempalte'"), "{}", warnings[0]);
+        // The warning points at the line of the misspelled key
+        let line = include_str!("../takure.toml").lines().count() + 5;
+        assert!(warnings[0].starts_with(&format!("{}:{}:", PATH, line)), "{}", warnings[0]);
+    }
+
+    #[test]
+    fn accepts_known_sink_keys() {
+        let warnings = warnings(concat!(
+            "[[sinks]]\ntype = 'webhook'\nurl = 'https://example.com/hook'\n",
+            "template = '{}'\non_failure = 'ignore'\n\n",
+            "[[sinks]]\ntype = 'command'\ncommand = ['notify']\ntimeout = 5\n",
+            "skip = [{ cards = ['E004*'] }]\n",
+        ));
+        assert!(warnings.is_empty(), "{:?}", warnings);
+    }
+}
@@ -0,0 +1,45 @@
+//! Loads the built library like another injected module would, and checks its C ABI
+#![cfg(target_os = "linux")]
+
+use libloading::{Library, Symbol};
+use std::ffi::c_void;
+use std::path::PathBuf;
+
+type Callback = extern "C" fn(event: *const c_void, user_data: *mut c_void);
+
+extern "C" fn callback(_event: *const c_void, _user_data: *mut c_void) {}
+
+fn library() -> Library {
+    // Cargo builds the library next to integration tests, in target/<profile>/deps
+    let path = std::env::current_exe()
+        .ok()
+        .and_then(|path| path.parent().map(|path| path.join("libtakure.so")))
+        .unwrap_or_else(|| PathBuf::from("libtakure.so"));
+
+    unsafe { Library::new(&path) }
+        .unwrap_or_else(|err| panic!("Could not load '{}': {}", path.display(), err))
+}
+
+#[test]
+fn registers_and_unregisters_callbacks() {
+    let library = library();
+    unsafe {
+        let version: Symbol<extern "C" fn() -> u32> = library.get(b"takure_abi_version\0").unwrap();
+        let register: Symbol<extern "C" fn(Option<Callback>, *mut c_void) -> i32> =
+            library.get(b"takure_register_callback\0").unwrap();
+        let unregister: Symbol<extern "C" fn(i32) -> i32> =
+            library.get(b"takure_unregister_callback\0").unwrap();
+
+        assert_eq!(version(), 1);
+        assert_eq!(register(None, std::ptr::null_mut()), -1);
+
+        let first = register(Some(callback), std::ptr::null_mut());
+        let second = register(Some(callback), std::ptr::null_mut());
+        assert!(first > 0);
+        assert_ne!(first, second);
+
+        assert_eq!(unregister(first), 0);
+        assert_eq!(unregister(first), -1);
+        assert_eq!(unregister(second), 0);
+    }
+}